@@ -0,0 +1,142 @@
+use Error;
+
+use std::f64::consts::LN_2;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+
+/// The magic bytes which mark the start of a ".bloom" file
+const MAGIC: &'static [u8; 8] = b"CATBLOOM";
+const HEADER_LEN: usize = 40;
+
+/// A bloom filter for the hashes of the keys in the main "key" file, which lets us skip
+/// the binary search for most of the keys that aren't there. The ".bloom" file starts
+/// with a 40-byte header (all integers in little-endian), followed by the bits,
+///
+/// | bytes    | field                                        |
+/// |----------|----------------------------------------------|
+/// | `0..8`   | magic (`CATBLOOM`)                           |
+/// | `8..12`  | number of hash functions                     |
+/// | `12..16` | (unused)                                     |
+/// | `16..24` | number of bits                               |
+/// | `24..32` | number of rows in the "key" file             |
+/// | `32..40` | hash of `HASHER_PROBE` (same as the header)  |
+///
+/// The last two are used to find out whether the filter belongs to the "key" file.
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    /// Create an empty filter for the given number of keys, with the given
+    /// false positive rate.
+    pub fn new(keys: u64, fp_rate: f64) -> BloomFilter {
+        let keys = keys.max(1) as f64;
+        let fp_rate = fp_rate.clamp(1e-12, 0.99);
+        let num_bits = (-keys * fp_rate.ln() / (LN_2 * LN_2)).ceil().max(64.0) as u64;
+        let num_hashes = (num_bits as f64 / keys * LN_2).round().max(1.0) as u32;
+        BloomFilter {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits: num_bits,
+            num_hashes: num_hashes,
+        }
+    }
+
+    /// The positions of the bits for the given hash (it's already a good hash, so we
+    /// derive the others from it, instead of hashing the key again).
+    fn positions(&self, hash: u64) -> impl Iterator<Item=u64> {
+        // the second one's mixed (so that it doesn't follow the first) and made odd
+        let mut h2 = hash.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        h2 = (h2 ^ (h2 >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        h2 = (h2 ^ (h2 >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        h2 = (h2 ^ (h2 >> 31)) | 1;
+
+        let num_bits = self.num_bits;
+        (0..self.num_hashes as u64).map(move |i| hash.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+
+    pub fn insert(&mut self, hash: u64) {
+        for pos in self.positions(hash) {
+            self.bits[(pos / 64) as usize] |= 1 << (pos % 64);
+        }
+    }
+
+    /// Check whether the hash could be in the filter (`false` means it's definitely not).
+    pub fn contains(&self, hash: u64) -> bool {
+        self.positions(hash).all(|pos| {
+            self.bits[(pos / 64) as usize] & (1 << (pos % 64)) != 0
+        })
+    }
+
+    /// Write the filter (for a "key" file with the given rows and hasher check) to the
+    /// given path, and sync it to the disk.
+    pub fn write_to(&self, path: &str, rows: u64, hasher_check: u64) -> Result<(), Error> {
+        let mut file = try!(File::create(path));
+        {
+            let mut writer = BufWriter::new(&mut file);
+            let mut header = [0; HEADER_LEN];
+            header[..8].copy_from_slice(MAGIC);
+            header[8..12].copy_from_slice(&self.num_hashes.to_le_bytes());
+            header[16..24].copy_from_slice(&self.num_bits.to_le_bytes());
+            header[24..32].copy_from_slice(&rows.to_le_bytes());
+            header[32..40].copy_from_slice(&hasher_check.to_le_bytes());
+            try!(writer.write_all(&header));
+            for word in &self.bits {
+                try!(writer.write_all(&word.to_le_bytes()));
+            }
+
+            try!(writer.flush());
+        }
+
+        file.sync_all().map_err(Error::Io)
+    }
+
+    /// Read the filter in the given path. This returns `None` if there's no such file, or
+    /// if the filter doesn't belong to the "key" file (with the given rows and hasher check),
+    /// in which case, it's better to not have a filter at all.
+    pub fn read_from(path: &str, rows: u64, hasher_check: u64)
+                     -> Result<Option<BloomFilter>, Error> {
+        let mut reader = match File::open(path) {
+            Ok(file) => BufReader::new(file),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::Io(e)),
+        };
+
+        let mut header = [0; HEADER_LEN];
+        match reader.read_exact(&mut header) {
+            Ok(_) => (),
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(Error::Io(e)),
+        }
+
+        let u64_at = |i: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&header[i..i + 8]);
+            u64::from_le_bytes(bytes)
+        };
+
+        let num_bits = u64_at(16);
+        if header[..8] != MAGIC[..] || num_bits == 0 ||
+           u64_at(24) != rows || u64_at(32) != hasher_check {
+            return Ok(None)
+        }
+
+        let mut filter = BloomFilter {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits: num_bits,
+            num_hashes: u32::from_le_bytes([header[8], header[9], header[10], header[11]]),
+        };
+
+        let mut word = [0; 8];
+        for bits in filter.bits.iter_mut() {
+            match reader.read_exact(&mut word) {
+                Ok(_) => *bits = u64::from_le_bytes(word),
+                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(Error::Io(e)),
+            }
+        }
+
+        Ok(Some(filter))
+    }
+}
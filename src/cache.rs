@@ -0,0 +1,125 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+/// The size of the blocks read from the "key" files (through the cache)
+pub const BLOCK_SIZE: u64 = 4096;
+
+/// The numbers from the cache of a [`HashFile`][hash-file] (see
+/// [`set_cache_size`][cache-size]).
+///
+/// [hash-file]: struct.HashFile.html
+/// [cache-size]: struct.HashFile.html#method.set_cache_size
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// The number of reads that found their stuff in the cache
+    pub hits: u64,
+    /// The number of reads that had to go to the files
+    pub misses: u64,
+    /// The number of bytes that are cached right now
+    pub bytes: usize,
+}
+
+/// An LRU cache (bounded in bytes) for the blocks of the "key" files and the records of the
+/// "data" file, which is shared by all the readers of a `HashFile`. The stuff is keyed by
+/// the file (each reader gets its own number) and the offset.
+pub struct BlockCache {
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+/// A cached block (or record), along with when it was last used.
+type Entry = (Arc<Vec<u8>>, u64);
+
+struct Inner {
+    entries: HashMap<(u64, u64), Entry>,                // (file, offset) => (bytes, last use)
+    recent: BTreeMap<u64, (u64, u64)>,                  // last use => (file, offset)
+    tick: u64,
+    next_file: u64,
+    stats: CacheStats,
+}
+
+impl BlockCache {
+    pub fn new(capacity: usize) -> BlockCache {
+        BlockCache {
+            capacity: capacity,
+            inner: Mutex::new(Inner {
+                entries: HashMap::new(),
+                recent: BTreeMap::new(),
+                tick: 0,
+                next_file: 0,
+                stats: CacheStats::default(),
+            }),
+        }
+    }
+
+    /// Run the function with the insides (a panic somewhere else can't leave them in
+    /// a bad state, so we don't care about poisoning).
+    fn with_inner<T, F: FnOnce(&mut Inner) -> T>(&self, f: F) -> T {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        f(&mut inner)
+    }
+
+    /// Get a number for a new file (the numbers are never reused, so a file that replaces
+    /// another one won't see its stuff).
+    pub fn next_file(&self) -> u64 {
+        self.with_inner(|inner| {
+            inner.next_file += 1;
+            inner.next_file
+        })
+    }
+
+    pub fn get(&self, file: u64, offset: u64) -> Option<Arc<Vec<u8>>> {
+        self.with_inner(|inner| {
+            inner.tick += 1;
+            let tick = inner.tick;
+            match inner.entries.get_mut(&(file, offset)) {
+                Some(&mut (ref bytes, ref mut last_use)) => {
+                    inner.recent.remove(last_use);
+                    inner.recent.insert(tick, (file, offset));
+                    *last_use = tick;
+                    inner.stats.hits += 1;
+                    Some(bytes.clone())
+                },
+                None => {
+                    inner.stats.misses += 1;
+                    None
+                },
+            }
+        })
+    }
+
+    /// Put the bytes into the cache (throwing out the least recently used stuff to
+    /// make room for them).
+    pub fn insert(&self, file: u64, offset: u64, bytes: Arc<Vec<u8>>) {
+        if bytes.len() > self.capacity {
+            return
+        }
+
+        self.with_inner(|inner| {
+            inner.tick += 1;
+            let tick = inner.tick;
+            inner.stats.bytes += bytes.len();
+            inner.recent.insert(tick, (file, offset));
+            if let Some((old, last_use)) = inner.entries.insert((file, offset), (bytes, tick)) {
+                inner.recent.remove(&last_use);
+                inner.stats.bytes -= old.len();
+            }
+
+            while inner.stats.bytes > self.capacity {
+                let (last_use, key) = match inner.recent.iter().next() {
+                    Some((&last_use, &key)) => (last_use, key),
+                    None => break,
+                };
+
+                inner.recent.remove(&last_use);
+                if let Some((old, _)) = inner.entries.remove(&key) {
+                    inner.stats.bytes -= old.len();
+                }
+            }
+        })
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.with_inner(|inner| inner.stats)
+    }
+}
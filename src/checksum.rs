@@ -0,0 +1,107 @@
+use {Error, SEP};
+
+use crc32c::{crc32c, crc32c_append};
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::str;
+
+/// Append the checksum (CRC32C, in hex) of the given record as its last field.
+pub fn with_checksum(line: &str) -> String {
+    format!("{}{}{:08x}", line, SEP, crc32c(line.as_bytes()))
+}
+
+/// Strip the checksum off a record that starts at the given offset of the file in the path,
+/// and make sure that the checksum matches.
+pub fn strip_checksum<'a>(line: &'a str, path: &str, offset: u64) -> Result<&'a str, Error> {
+    // the keys and values are escaped, so the last null byte is ours
+    let mut split = line.rsplitn(2, SEP);
+    let (checksum, body) = (split.next(), split.next());
+    match (checksum.and_then(|c| u32::from_str_radix(c, 16).ok()), body) {
+        (Some(checksum), Some(body)) if crc32c(body.as_bytes()) == checksum => Ok(body),
+        _ => Err(Error::ChecksumMismatch {
+            path: path.to_owned(),
+            offset: offset,
+        }),
+    }
+}
+
+/// A writer which keeps track of the checksum of everything that goes through it.
+pub struct ChecksumWriter<W: Write> {
+    inner: W,
+    checksum: u32,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    pub fn new(inner: W) -> ChecksumWriter<W> {
+        ChecksumWriter {
+            inner: inner,
+            checksum: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> (W, u32) {
+        (self.inner, self.checksum)
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = try!(self.inner.write(buf));
+        self.checksum = crc32c_append(self.checksum, &buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The damage found by [`HashFile::verify`][verify].
+///
+/// [verify]: struct.HashFile.html#method.verify
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Verification {
+    /// The "key" files (the main one, or the runs) whose checksums don't match the ones in
+    /// their headers (i.e., something's changed, even if all the rows look fine).
+    pub bad_files: Vec<String>,
+    /// The rows whose checksums don't match, as the paths of their files and their offsets.
+    pub bad_rows: Vec<(String, u64)>,
+    /// The offsets of the records (the keys and the values, in the "data" file) whose
+    /// checksums don't match.
+    pub bad_records: Vec<u64>,
+}
+
+impl Verification {
+    /// Whether everything's fine.
+    pub fn is_ok(&self) -> bool {
+        self.bad_files.is_empty() && self.bad_rows.is_empty() && self.bad_records.is_empty()
+    }
+}
+
+/// Check the records in the "data" file (in the given path) against their checksums, and
+/// note down the ones that don't match in the report.
+pub fn verify_records(path: &str, report: &mut Verification) -> Result<(), Error> {
+    let mut reader = BufReader::new(try!(File::open(path)));
+    let mut line = vec![];
+    let mut offset = 0;
+    loop {
+        line.clear();
+        let n = try!(reader.read_until(b'\n', &mut line));
+        if n == 0 {
+            break
+        }
+
+        // (a record that's been cut short doesn't have its newline)
+        let is_good = line.pop() == Some(b'\n') && str::from_utf8(&line).is_ok_and(|v| {
+            strip_checksum(v, path, offset).is_ok()
+        });
+        if !is_good {
+            report.bad_records.push(offset);
+        }
+
+        offset += n as u64;
+    }
+
+    Ok(())
+}
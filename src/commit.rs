@@ -0,0 +1,164 @@
+use Error;
+use helpers::{dir_of, sync_dir};
+use run::TEMP_SUFFIX;
+
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::path::Path;
+
+/// The suffix of the manifest, which lives alongside the other files while a commit is
+/// in progress
+pub const MANIFEST_SUFFIX: &'static str = ".manifest";
+/// The first line of a manifest (the number's bumped whenever the layout changes)
+const MANIFEST_MAGIC: &'static str = "catalog manifest 1";
+
+/// A bunch of changes to the files of a `HashFile` (renaming the temp files over the old
+/// ones, and removing the files that we don't need anymore), which go through together.
+///
+/// Once the temp files are synced, the changes are written to a manifest (as a temp file,
+/// which is synced and renamed to `<path>.manifest`), and the directory is synced. That's
+/// the point where the commit goes through - after that, the changes are made, the
+/// directory is synced once again, and the manifest is removed. If we go down in between,
+/// then the manifest is still there, and `recover` makes the rest of the changes (so, we
+/// always end up with either the old files or the new ones).
+///
+/// The manifest has a line for each change (with the names of the files, which are in the
+/// same directory as the main "key" file),
+///
+/// ``` text
+/// catalog manifest 1
+/// rename\0SAMPLE.dat.hash_file\0SAMPLE.dat
+/// rename\0SAMPLE.hash_file\0SAMPLE
+/// remove\0SAMPLE.run.3
+/// ```
+pub struct Commit {
+    renames: Vec<(String, String)>,     // (from, to)
+    removals: Vec<String>,
+}
+
+impl Commit {
+    pub fn new() -> Commit {
+        Commit {
+            renames: vec![],
+            removals: vec![],
+        }
+    }
+
+    /// Rename the (already synced) file in the first path to the second one.
+    pub fn rename(&mut self, from: &str, to: &str) {
+        self.renames.push((file_name(from), file_name(to)));
+    }
+
+    /// Remove the file in the path (if it's there).
+    pub fn remove(&mut self, path: &str) {
+        self.removals.push(file_name(path));
+    }
+
+    /// Make the changes to the files of the `HashFile` in the given path.
+    pub fn apply(self, path: &str) -> Result<(), Error> {
+        // a rename is atomic on its own
+        if self.renames.len() + self.removals.len() == 1 {
+            try!(self.replay(path));
+            return sync_dir(path)
+        }
+
+        let manifest_path = format!("{}{}", path, MANIFEST_SUFFIX);
+        let temp_path = format!("{}{}", manifest_path, TEMP_SUFFIX);
+        {
+            let mut file = try!(File::create(&temp_path));
+            {
+                let mut writer = BufWriter::new(&mut file);
+                try!(writeln!(writer, "{}", MANIFEST_MAGIC));
+                for (from, to) in &self.renames {
+                    try!(writeln!(writer, "rename\0{}\0{}", from, to));
+                }
+
+                for name in &self.removals {
+                    try!(writeln!(writer, "remove\0{}", name));
+                }
+
+                try!(writer.flush());
+            }
+
+            try!(file.sync_all());
+        }
+
+        try!(fs::rename(&temp_path, &manifest_path));
+        try!(sync_dir(path));
+
+        // it's gone through (the rest can be done by `recover`, if we go down now)
+        try!(self.replay(path));
+        try!(sync_dir(path));
+        try!(fs::remove_file(&manifest_path));
+        sync_dir(path)
+    }
+
+    /// Make the changes which haven't been made yet (the files which were renamed (or
+    /// removed) before we went down won't be there anymore).
+    fn replay(&self, path: &str) -> Result<(), Error> {
+        let dir = dir_of(path);
+        for (from, to) in &self.renames {
+            match fs::rename(dir.join(from), dir.join(to)) {
+                Ok(_) => (),
+                Err(ref e) if e.kind() == ErrorKind::NotFound => (),
+                Err(e) => return Err(Error::Io(e)),
+            }
+        }
+
+        for name in &self.removals {
+            match fs::remove_file(dir.join(name)) {
+                Ok(_) => (),
+                Err(ref e) if e.kind() == ErrorKind::NotFound => (),
+                Err(e) => return Err(Error::Io(e)),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Get the name of the file in the path (all our files are in the same directory).
+fn file_name(path: &str) -> String {
+    Path::new(path).file_name().and_then(|n| n.to_str()).unwrap_or(path).to_owned()
+}
+
+/// Finish the commit that was going on when we went down (if there was one) for the
+/// `HashFile` in the given path. Returns whether there was one.
+pub fn finish_commit(path: &str) -> Result<bool, Error> {
+    let manifest_path = format!("{}{}", path, MANIFEST_SUFFIX);
+    let file = match File::open(&manifest_path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(Error::Io(e)),
+    };
+
+    let mut lines = BufReader::new(file).lines();
+    match lines.next() {
+        Some(Ok(ref line)) if line == MANIFEST_MAGIC => (),
+        Some(Err(e)) => return Err(Error::Io(e)),
+        _ => return Err(Error::InvalidHeader(manifest_path)),
+    }
+
+    let mut commit = Commit::new();
+    let mut offset = MANIFEST_MAGIC.len() as u64 + 1;
+    for line in lines {
+        let line = try!(line);
+        let fields = line.split('\0').collect::<Vec<_>>();
+        match fields[..] {
+            ["rename", from, to] => commit.renames.push((from.to_owned(), to.to_owned())),
+            ["remove", name] => commit.removals.push(name.to_owned()),
+            _ => return Err(Error::CorruptRow {
+                path: manifest_path,
+                offset: offset,
+            }),
+        }
+
+        offset += line.len() as u64 + 1;
+    }
+
+    try!(commit.replay(path));
+    try!(sync_dir(path));
+    try!(fs::remove_file(&manifest_path));
+    try!(sync_dir(path));
+    Ok(true)
+}
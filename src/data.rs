@@ -0,0 +1,72 @@
+use {Error, SEP};
+use cache::BlockCache;
+use checksum::{strip_checksum, with_checksum};
+use reader::LineReader;
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::sync::Arc;
+
+/// Put together a record for the "data" file - the (encoded and escaped) key, followed by
+/// the value (unless it's a tombstone) and the checksum, like `key\0value\0checksum`.
+pub fn record(key: &str, value: Option<&str>) -> String {
+    match value {
+        Some(value) => with_checksum(&format!("{}{}{}", key, SEP, value)),
+        None => with_checksum(key),
+    }
+}
+
+/// Reads the records (i.e., the keys and the values) from the "data" file.
+pub struct DataReader {
+    path: String,
+    reader: LineReader,
+}
+
+impl DataReader {
+    pub fn open(path: &str) -> Result<DataReader, Error> {
+        Ok(DataReader {
+            path: path.to_owned(),
+            reader: try!(LineReader::new(try!(File::open(path)), path)),
+        })
+    }
+
+    /// Map the file once again (see `LineReader::remap`).
+    pub fn remap(&mut self) -> Result<(), Error> {
+        self.reader.remap()
+    }
+
+    /// Cache the records we read from the file.
+    pub fn set_cache(&mut self, cache: Option<Arc<BlockCache>>) {
+        self.reader.set_cache(cache);
+    }
+
+    /// Get the record at the given offset as it is (so that it can be copied elsewhere),
+    /// making sure that its checksum matches (so that we don't copy the damage around).
+    pub fn line_at<'a>(&'a self, offset: u64) -> Result<Cow<'a, str>, Error> {
+        let line = try!(self.reader.line_at(offset));
+        try!(strip_checksum(&line, &self.path, offset));
+        Ok(line)
+    }
+
+    /// Get the key and the value (unless it's a tombstone) of the record at the given
+    /// offset, making sure that its checksum matches.
+    pub fn record_at(&self, offset: u64) -> Result<(String, Option<String>), Error> {
+        let line = try!(self.reader.line_at(offset));
+        let record = try!(strip_checksum(&line, &self.path, offset));
+        // the key's escaped, so the first null byte is where it ends
+        let mut split = record.splitn(2, SEP);
+        Ok((split.next().unwrap_or("").to_owned(), split.next().map(|v| v.to_owned())))
+    }
+
+    /// Get the key and the value of the record at the given offset (it should have a value,
+    /// i.e., it shouldn't be a tombstone).
+    pub fn entry_at(&self, offset: u64) -> Result<(String, String), Error> {
+        match try!(self.record_at(offset)) {
+            (key, Some(value)) => Ok((key, value)),
+            (_, None) => Err(Error::CorruptRow {
+                path: self.path.clone(),
+                offset: offset,
+            }),
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use Error;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+#[cfg(feature = "serde")]
+use serde::de::DeserializeOwned;
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Converts the keys and values into the strings that go into the files (and back).
+///
+/// The strings are escaped before they're written, so they can have anything in them
+/// (newlines, null bytes, etc.)
+pub trait Encoding<T> {
+    /// Encode the thing into a string.
+    fn encode(&self, thing: &T) -> Result<String, Error>;
+
+    /// Decode the thing from a string (returns `None` if it can't be done).
+    fn decode(&self, s: &str) -> Option<T>;
+}
+
+/// The default encoding, which makes use of the `Display` and `FromStr` impls of the types.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Text;
+
+impl<T: Display + FromStr> Encoding<T> for Text {
+    fn encode(&self, thing: &T) -> Result<String, Error> {
+        Ok(thing.to_string())
+    }
+
+    fn decode(&self, s: &str) -> Option<T> {
+        s.parse::<T>().ok()
+    }
+}
+
+/// An encoding for anything that can be (de)serialized with `serde`, which stores
+/// the stuff as JSON (requires the `serde` feature).
+#[cfg(feature = "serde")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+#[cfg(feature = "serde")]
+impl<T: Serialize + DeserializeOwned> Encoding<T> for Json {
+    fn encode(&self, thing: &T) -> Result<String, Error> {
+        ::serde_json::to_string(thing).map_err(|e| Error::Encode(e.to_string()))
+    }
+
+    fn decode(&self, s: &str) -> Option<T> {
+        ::serde_json::from_str(s).ok()
+    }
+}
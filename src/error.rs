@@ -0,0 +1,85 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+/// The error type for all the operations on a [`HashFile`][hash-file].
+///
+/// [hash-file]: struct.HashFile.html
+#[derive(Debug)]
+pub enum Error {
+    /// Something went wrong while reading/writing the underlying files.
+    Io(io::Error),
+    /// The given string (found in the "key" file) couldn't be parsed into the key type.
+    KeyParse(String),
+    /// The given string (found in the "data" file) couldn't be parsed into the value type.
+    ValueParse(String),
+    /// A key or value couldn't be encoded (with the reason given by the encoder).
+    Encode(String),
+    /// The row starting at the given byte offset of the file (in the path) is malformed.
+    CorruptRow {
+        path: String,
+        offset: u64,
+    },
+    /// The checksum of the row (or the value) starting at the given byte offset of the file
+    /// (in the path) doesn't match (i.e., the file's been damaged).
+    ChecksumMismatch {
+        path: String,
+        offset: u64,
+    },
+    /// The file in the given path doesn't have a valid header (i.e., it's not a "key" file).
+    InvalidHeader(String),
+    /// The file was written in a format version that we don't know about.
+    UnsupportedVersion(u16),
+    /// The file was written using a hash algorithm that we don't know about.
+    UnsupportedHasher(u8),
+    /// The file was built with a different hasher (or the same one, with different keys).
+    HasherMismatch,
+    /// The file in the given path is locked by someone else (i.e., it's being written, or
+    /// it's being read while we're trying to write to it).
+    Locked(String),
+    /// The file in the given path was left in the middle of a commit (by a crash), and it
+    /// has to be recovered before it can be read (see [`recover`][recover]).
+    ///
+    /// [recover]: fn.recover.html
+    NeedsRecovery(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "I/O error ({})", e),
+            Error::KeyParse(ref s) => write!(f, "Cannot parse the key from {:?}", s),
+            Error::ValueParse(ref s) => write!(f, "Cannot parse the value from {:?}", s),
+            Error::Encode(ref s) => write!(f, "Cannot encode the key/value ({})", s),
+            Error::CorruptRow { ref path, offset } =>
+                write!(f, "Found a corrupt row at offset {} in {}", offset, path),
+            Error::ChecksumMismatch { ref path, offset } =>
+                write!(f, "Checksum mismatch at offset {} in {}", offset, path),
+            Error::InvalidHeader(ref path) =>
+                write!(f, "Cannot find a valid header in {} (not a catalog file?)", path),
+            Error::UnsupportedVersion(v) => write!(f, "Unsupported format version ({})", v),
+            Error::UnsupportedHasher(id) => write!(f, "Unsupported hash algorithm ({})", id),
+            Error::HasherMismatch =>
+                write!(f, "The file was built with a different hasher (or different keys)"),
+            Error::Locked(ref path) => write!(f, "The file in {} is locked by someone else", path),
+            Error::NeedsRecovery(ref path) =>
+                write!(f, "The file in {} needs recovery (open it with `HashFile::new`, \
+                           or call `recover`)", path),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
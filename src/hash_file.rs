@@ -1,76 +1,91 @@
-use helpers::{create_or_open_file, hash, get_size};
-use helpers::{read_one_line, seek_from_start, write_buffer};
+use bloom::BloomFilter;
+use cache::{BlockCache, CacheStats};
+use checksum::{Verification, verify_records};
+use data::{DataReader, record};
+use commit::Commit;
+use encoding::{Encoding, Text};
+use hasher::DefaultStableHasher;
+use header::{FLAG_FINISHED, HASHER_PROBE};
+use helpers::{create_or_open_file, dir_of, escape, hash, get_size, unescape};
+use helpers::{seek_from_start, write_buffer};
+use lock::lock;
+use recovery::{Recovery, recover_locked};
+use run::{KeyIndex, Merged, Rows, Run, RunWriter, TEMP_SUFFIX, lookup};
+use search::SearchMode;
+use spill::{SpillEntry, SpillReader, write_spill};
+use wal::{WAL_SUFFIX, append_log, open_log, read_log};
 
-use {SEP};
+use Error;
 
-use std::cmp::Ordering;
-use std::collections::BTreeMap;
-use std::error::Error;
-use std::fmt::{self, Display};
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::collections::btree_map::Entry;
+use std::fmt::Display;
 use std::fs::{self, File};
-use std::hash::{Hash, Hasher};
-use std::io::{BufRead, BufReader, BufWriter};
+use std::hash::BuildHasher;
+use std::io::BufWriter;
+use std::iter;
+use std::marker::PhantomData;
 use std::mem;
-use std::ops::AddAssign;
+use std::path::Path;
 use std::str::FromStr;
+use std::sync::Arc;
 
-const TEMP_SUFFIX: &'static str = ".hash_file";
-const DAT_SUFFIX: &'static str = ".dat";
+pub const DAT_SUFFIX: &'static str = ".dat";
+pub const BLOOM_SUFFIX: &'static str = ".bloom";
+pub const RUN_SUFFIX: &'static str = ".run.";
+pub const SPILL_SUFFIX: &'static str = ".spill.";
 
-// FIXME: have a bool for marking key/vals to be removed (required for `remove` method)
-struct KeyIndex<K: Display + FromStr + Hash> {
-    key: K,
-    idx: u64,
-    count: usize,
-}
+/// Find the runs lying around for the "key" file in the given path, along with their
+/// sequence numbers (sorted from the oldest to the newest).
+pub fn find_runs(path: &str) -> Result<Vec<(u64, String)>, Error> {
+    let prefix = match Path::new(path).file_name().and_then(|n| n.to_str()) {
+        Some(name) => format!("{}{}", name, RUN_SUFFIX),
+        None => return Ok(vec![]),
+    };
 
-impl<K: Display + FromStr + Hash> KeyIndex<K> {
-    pub fn new(key: K) -> KeyIndex<K> {
-        KeyIndex {
-            key: key,
-            idx: 0,
-            count: 0,
+    let mut runs = vec![];
+    for entry in try!(fs::read_dir(dir_of(path))) {
+        let name = try!(entry).file_name();
+        // temp files (and whatever else) won't have a number at the end
+        let seq = name.to_str().and_then(|n| n.strip_prefix(&prefix))
+                               .and_then(|n| n.parse::<u64>().ok());
+        if let Some(seq) = seq {
+            runs.push((seq, format!("{}{}{}", path, RUN_SUFFIX, seq)));
         }
     }
-}
 
-// FIXME: This should be changed to serialization
-impl<K: Display + FromStr + Hash> Display for KeyIndex<K> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}{}{}{}{}", self.key, SEP, self.idx, SEP, self.count)
-    }
+    runs.sort();
+    Ok(runs)
 }
 
-impl<K: Display + FromStr + Hash> FromStr for KeyIndex<K> {
-    type Err = String;
-
-    fn from_str(s: &str) -> Result<KeyIndex<K>, String> {
-        let mut split = s.split(SEP);
-        Ok(KeyIndex {
-            key: try!(split.next().unwrap_or("")
-                                  .parse::<K>()
-                                  .map_err(|_| format!("Cannot parse the key!"))),
-            idx: try!(split.next().unwrap_or("")
-                                  .parse::<u64>()
-                                  .map_err(|_| format!("Cannot parse the index!"))),
-            count: try!(split.next().unwrap_or("")
-                                    .parse::<usize>()
-                                    .map_err(|_| format!("Cannot parse 'overwritten' count"))),
-        })
-    }
+/// Load the bloom filter of the main "key" file in the given path (if it has one, and if it
+/// still belongs to the file).
+pub fn load_bloom<S: BuildHasher>(path: &str, base: &mut Run, hasher: &S) -> Result<(), Error> {
+    let bloom_path = format!("{}{}", path, BLOOM_SUFFIX);
+    base.bloom = try!(BloomFilter::read_from(&bloom_path, base.rows, hash(hasher, HASHER_PROBE)));
+    Ok(())
 }
 
-impl<K: Display + FromStr + Hash> AddAssign for KeyIndex<K> {
-    fn add_assign(&mut self, other: KeyIndex<K>) {
-        self.idx = other.idx;
-        self.count += 1;
-    }
-}
+/// The rows of the keys which are alive, along with their values if they're still in memory.
+type AliveRows<'a> = Box<dyn Iterator<Item=Result<(KeyIndex, Option<&'a String>), Error>> + 'a>;
 
-impl<K: Display + FromStr + Hash> Hash for KeyIndex<K> {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.key.hash(state);
+/// The sorted entries from one of the chunks of `build`.
+type SpillSource = Box<dyn Iterator<Item=Result<SpillEntry, Error>>>;
+
+/// The next entry of a chunk in the heap of `build`, as (hash, key, chunk, count, value).
+type HeapEntry = Reverse<(u64, String, usize, usize, String)>;
+
+/// Put the next entry from the given source (if any) into the heap.
+fn push_next(heap: &mut BinaryHeap<HeapEntry>, sources: &mut [SpillSource],
+             source: usize) -> Result<(), Error> {
+    if let Some(entry) = sources[source].next() {
+        let entry = try!(entry);
+        heap.push(Reverse((entry.hash, entry.key, source, entry.count, entry.value)));
     }
+
+    Ok(())
 }
 
 /// An implementation of a "file-based" map which stores key-value pairs in sorted fashion in
@@ -78,29 +93,67 @@ impl<K: Display + FromStr + Hash> Hash for KeyIndex<K> {
 ///
 /// # Design
 ///
-/// `HashFile` maintains two files - one for the keys and value indices, and the other
-/// for the values themselves. Since it uses padding to maintain constant line length
-/// throughout the file, large values can lead to extremely huge and sparse files. Having two
-/// files eliminates this problem.
+/// `HashFile` maintains two files - one for the hashes of the keys and value indices, and
+/// the other for the keys and values themselves. Since the rows in the "key" file have a
+/// fixed width, we can seek to any of them right away, and the keys and values (which can
+/// be as large as they want) don't get in the way.
+///
+/// - During insertion, the key is encoded, and the hash of the encoded bytes is obtained (using
+/// the built-in [`SipHasher`][hasher] by default, or whichever hasher the `HashFile` has been
+/// given), which acts as the key for sorting. Since different keys
+/// can (rarely) have the same hash, the encoded key is used to break the tie, and so the
+/// colliding keys simply end up in adjacent rows.
+///
+/// - Flushing doesn't touch the rows we already have in the files. The keys and values in the
+/// underlying map are appended to the "data" file, and their rows are written in ascending order
+/// (of the hashes) to a new "run" - a smaller "key" file (named like `SAMPLE.run.3`) which
+/// lives alongside the main one. Whenever a run grows as big as the one before it, the two
+/// are merged (and eventually, they end up in the main file), so that there are only a handful
+/// of runs at any given moment, and each row is rewritten only a few times. All the runs
+/// can also be merged into the main file by calling [`compact`][compact].
 ///
-/// - During insertion, the hash of the supplied key is obtained (using the built-in
-/// [`SipHasher`][hasher]), which acts as the key for sorting.
+/// - Each row in the "key" file (and in each run) is 24 bytes long - the hash of the key,
+/// the offset of its record in the "data" file, and the counter (along with whether the key's
+/// been removed), so that `get` can compare the hashes without parsing anything. The "data"
+/// file has a record for each flush of a key (its key and value, separated by a null byte) on
+/// each line, and the key is read from there only when a row has the same hash as the key
+/// we're looking for (the value comes along with it, so a hit reads the record once). Calling
+/// the [`finish`][finish] method merges everything into the main file, and also does a cleanup
+/// by getting rid of unnecessary records from the "data" file. The backslashes, newlines,
+/// carriage returns and null bytes in the keys and values are escaped (as `\\`, `\n`, `\r`
+/// and `\0`) before they're written, so that anything we throw at it will make it back in
+/// one piece.
 ///
-/// - While flushing, two iterators (one for the key/values in the underlying map, another
-/// for the keys in the file) and two writers (one for key-index, another for the values)
-/// are maintained. The readers throw key/values in ascending order, the hashes of the keys
-/// are compared, the values are appended to a temporary file, and the keys (along with the
-/// value indices) are written to another temporary file. In the end, both the files are renamed.
+/// - Each row (and each record) ends with a CRC32C of the stuff before it, so that a damaged
+/// row (or record) is caught when we read it, instead of leading us astray. The header of
+/// each "key" file has a checksum for the whole file, and [`verify`][verify] checks all
+/// of them in one go.
 ///
-/// - Basically, the files are in DSV format - one has keys and value indices separated by a
-/// null byte, while the other has values separated by `\n`. Each line in the "key" file is
-/// ensured to have the same length, by properly padding it with null bytes, which is done by
-/// calling the [`finish`][finish] method, which also does a cleanup and gets rid of unnecessary
-/// values from the "data" file.
+/// - The "key" file (and each run) starts with a 40-byte header, which has some magic bytes,
+/// the format version, the hash algorithm, the row width, the number of rows, some flags and
+/// the checksum. The header is checked while opening the file, so that we don't end up
+/// messing with some random file (or a file written in a format we don't understand).
 ///
 /// - While getting, the hash for the given key is computed, and a [binary search][search]
-/// is made by seeking through the file. The value index is found in O(log-n) time, and the value
-/// is obtained from the "data" file in O(1) time.
+/// is made by seeking through the runs (from the newest to the oldest) and the main file.
+/// The value index is found in O(log-n) time, and the value is obtained from the "data" file
+/// in O(1) time.
+///
+/// - Removing a key doesn't touch the files right away. Instead, a "tombstone" is flushed along
+/// with the other keys (the counter in each row of the "key" file marks whether the key has
+/// been removed), which hides the key (and its values in the older runs) from `get`, until it's
+/// merged into the main file, where it's dropped for good.
+///
+/// - Files are never modified in place. The new ones are written to temp files (with a
+/// `.hash_file` suffix), which are synced to the disk before they're renamed over the old
+/// ones. When a bunch of files are replaced together (say, by a merge), the renames (and the
+/// removals) are first written to a `.manifest` file, so that if we go down halfway, then
+/// the next `HashFile::new` can finish the job. Either way, we get the old files, or the new
+/// ones (but never a mix of them).
+///
+/// - Only one `HashFile` can be open on the files at a time. It holds an exclusive lock (on
+/// a `.lock` file) until it's dropped, and the readers hold a shared one, so opening a
+/// `HashFile` while someone else is using the files fails with `Error::Locked`.
 ///
 /// # Examples
 ///
@@ -121,8 +174,7 @@ impl<K: Display + FromStr + Hash> Hash for KeyIndex<K> {
 /// let mut hash_file: HashFile<usize, _> =
 ///     try!(HashFile::new("/tmp/SAMPLE").map(|hf| hf.set_capacity(100)));
 ///
-/// // We don't have to mention all the types explicitly, leaving it to type inference
-/// // But, there's a reason why I mentioned `usize` (which we'll see in a moment).
+/// // We don't have to mention all the types explicitly, leaving it to type inference.
 ///
 /// // Insert some stuff into the map (in this case, integers and upper case alphabets)
 /// for i in 0..1000 {
@@ -132,8 +184,8 @@ impl<K: Display + FromStr + Hash> Hash for KeyIndex<K> {
 /// // This flushes the data to the file for every 100 key/value pairs, since
 /// // we've set the capacity to 100.
 ///
-/// // Call the finish method once you're done with insertion. It's necessary
-/// // because it pads each line and ensures that all the lines have the same length.
+/// // Call the finish method once you're done with insertion. It flushes the rest of
+/// // the stuff, and merges everything into one file.
 /// try!(hf.finish());
 ///
 /// // Now, we're ready to "get" the values.
@@ -156,31 +208,28 @@ impl<K: Display + FromStr + Hash> Hash for KeyIndex<K> {
 /// Now, let's have a quick peek inside the generated file.
 ///
 /// ``` bash
-/// $ head -5 /tmp/SAMPLE*
-/// ==> /tmp/SAMPLE <==
-/// 68600
-/// 18320
-/// 59540
-/// 50060
-/// 1580
-///
-/// ==> /tmp/SAMPLE.dat <==
-/// K
-/// B
-/// X
-/// G
-/// P
-/// $ wc -l /tmp/SAMPLE*
-///  1000 /tmp/SAMPLE
-///  1000 /tmp/SAMPLE.dat
-///  2000 total
+/// $ head -c 8 /tmp/SAMPLE
+/// CATALOG
+/// $ xxd -s 40 -c 24 -l 72 /tmp/SAMPLE
+/// 00000028: 99e2 0129 1968 0e00 0000 0000 0000 0000 0000 0000 f374 65ec  ...).h...............te.
+/// 00000040: d5f7 29be f52a aa00 0f00 0000 0000 0000 0000 0000 7be7 91ba  ..)..*..............{...
+/// 00000058: ec91 5f40 f299 d400 1e00 0000 0000 0000 0000 0000 f942 c395  .._@.................B..
+/// $ head -5 /tmp/SAMPLE.dat | tr '\0' ' '
+/// 759 F 8a844279
+/// 818 M a07f4f1a
+/// 640 Q 36548852
+/// 646 W c14499ad
+/// 86 I f9fc3b6e
+/// $ wc -l /tmp/SAMPLE.dat
+/// 1000 /tmp/SAMPLE.dat
 /// $ ls -l /tmp/SAMPLE*
-/// -rw-rw-r-- 1 user user 11000 Jul 09 22:10 /tmp/SAMPLE
-/// -rw-rw-r-- 1 user user 2000 Jul 09 22:10 /tmp/SAMPLE.dat
+/// -rw-rw-r-- 1 user user 24040 Jul 09 22:10 /tmp/SAMPLE
+/// -rw-rw-r-- 1 user user 14890 Jul 09 22:10 /tmp/SAMPLE.dat
+/// -rw-rw-r-- 1 user user     0 Jul 09 22:10 /tmp/SAMPLE.lock
 /// ```
 ///
-/// The file sizes will (and should!) always be a multiple of the number of
-/// key/value pairs, since each line is padded to have the same length.
+/// Leaving the header aside, the size of the "key" file will (and should!) always be
+/// a multiple of the number of key/value pairs, since each row is 24 bytes long.
 /// Now, we can have another program to get the key/value pairs.
 ///
 /// ``` rust
@@ -188,18 +237,13 @@ impl<K: Display + FromStr + Hash> Hash for KeyIndex<K> {
 /// let mut hf: HashFile<usize, String> = try!(HashFile::new("/tmp/SAMPLE"));
 /// ```
 ///
-/// A couple of things to note here. Before getting, we need to mention the types,
-/// because `rustc` doesn't know what type we have in the file (and, it'll throw an error).
+/// Note that before getting, we need to mention the types, because `rustc` doesn't know
+/// what type we have in the file (and, it'll throw an error).
 ///
-/// Moreover, if we hadn't explicitly mentioned `usize` during insertion,
-/// `rustc` would've gone for some default type, and if we mention some other primitive
-/// now, the hashes won't match i.e., `hash(0u32) != hash(0usize)`.
-///
-/// For example, `"2"` can be parsed to all the integer primitives (`u8`, `u64`, `isize`, etc.),
-/// but, they all produce different hashes. In such a case, it's more likely that `HashFile`
-/// returns `None` while getting the value corresponding to a key, even if it exists in
-/// the file. Hence, it's up to the user to handle such cases
-/// (by manually denoting the type during insertion and getting).
+/// The hashes are computed from the encoded keys (i.e., the stuff that goes into the file),
+/// and not from the Rust types themselves. So, it doesn't matter whether we'd inserted `usize`
+/// or `u32` (or on which platform we'd built the file) - `"2"` is `"2"` either way. As long as
+/// the key's encoded form is the same, we'll find its value.
 ///
 /// ``` rust
 /// // Now, we can get the values...
@@ -214,7 +258,8 @@ impl<K: Display + FromStr + Hash> Hash for KeyIndex<K> {
 /// We've used a lot of `try!` here, because each method invocation involves making OS
 /// calls for manipulating the underlying file descriptor. Since all the methods have been
 /// ensured to return a [`Result<T, E>`][result], `HashFile` can be guaranteed from
-/// panicking along the run.
+/// panicking along the run. The [`Error`][error] tells us what went wrong - whether it's
+/// the OS complaining about the files, or something in the files that we couldn't understand.
 ///
 /// # Advantages:
 /// - **Control over memory:** You're planning to put a great deal of "stuff" into a map, but you
@@ -229,27 +274,43 @@ impl<K: Display + FromStr + Hash> Hash for KeyIndex<K> {
 /// # Drawbacks:
 /// - **Sluggish insertion:** Re-allocation in memory is lightning fast, while putting stuff into
 ///  the usual maps, and so it won't be obvious during the execution of a program. But, that's not
-/// the case when it comes to file. Flushing to a file takes time (as it makes OS calls), and it
-/// increases exponentially as O(2<sup>n</sup>) during insertion, which would be *very* obvious
+/// the case when it comes to file. Flushing to a file takes time (as it makes OS calls), and
+/// each row gets rewritten O(log-n) times as the runs are merged, which would be *very* obvious
 /// in our case.
 ///
+/// [compact]: #method.compact
+/// [error]: enum.Error.html
 /// [finish]: #method.finish
 /// [hasher]: https://docs.rs/siphasher/%5E0.2/siphasher/sip/struct.SipHasher.html
 /// [result]: https://doc.rust-lang.org/std/result/enum.Result.html
 /// [search]: https://en.wikipedia.org/wiki/Binary_search_algorithm
-pub struct HashFile<K: Display + FromStr + Hash, V: Display + FromStr> {
-    file: File,
+/// [verify]: #method.verify
+pub struct HashFile<K, V, E: Encoding<K> + Encoding<V> = Text,
+                    S: BuildHasher = DefaultStableHasher> {
     path: String,
-    size: u64,
+    base: Run,          // the main "key" file
+    runs: Vec<Run>,     // from the oldest to the newest
+    next_run: u64,
     data_file: File,
+    data_reader: DataReader,
     data_path: String,
     data_idx: u64,
-    hashed: BTreeMap<u64, (KeyIndex<K>, V)>,
+    // encoded (and escaped) values, so that they're ready to be written
+    hashed: BTreeMap<(u64, String), (KeyIndex, Option<String>)>,
     capacity: usize,
-    line_length: usize,
+    bloom_fp_rate: Option<f64>,     // write a bloom filter for the main file (if set)
+    search_mode: SearchMode,
+    fence_every: u64,               // rows per fence (or zero, if we don't keep them)
+    cache: Option<Arc<BlockCache>>,
+    log: Option<File>,              // write-ahead log (if we're keeping one)
+    recovery: Recovery,
+    _lock: Option<File>,            // (exclusive) lock, which goes away when we're dropped
+    encoding: E,
+    hasher: S,
+    _marker: PhantomData<(K, V)>,
 }
 
-impl<K: Display + FromStr + Hash, V: Display + FromStr> HashFile<K, V> {
+impl<K: Display + FromStr, V: Display + FromStr> HashFile<K, V> {
     /// Create a new `HashFile` for mapping key/value pairs in the given path.
     ///
     /// ``` rust
@@ -257,33 +318,178 @@ impl<K: Display + FromStr + Hash, V: Display + FromStr> HashFile<K, V> {
     /// ```
     ///
     /// This will maintain two files - `SAMPLE` and `SAMPLE.dat` in `/tmp/`.
-    /// The latter has the values, while the former has the keys (sorted by its hash)
-    /// along with the value indices and overwritten count.
-    pub fn new(path: &str) -> Result<HashFile<K, V>, String> {
-        let mut file = try!(create_or_open_file(&path));
-        let file_size = get_size(&file).unwrap_or(0);
+    /// The latter has the keys and values, while the former has the hashes of the keys
+    /// (sorted) along with the value indices and overwritten count.
+    ///
+    /// If the file already exists, then its header (and the headers of its runs)
+    /// is checked, and this fails with
+    /// `Error::InvalidHeader` (or `Error::UnsupportedVersion`) if it's not something
+    /// that we can work with. If the files are already open (in this process, or in
+    /// some other), then this fails with `Error::Locked`.
+    pub fn new(path: &str) -> Result<HashFile<K, V>, Error> {
+        HashFile::with_encoding(path, Text)
+    }
+}
+
+impl<K, V, E: Encoding<K> + Encoding<V>> HashFile<K, V, E> {
+    /// Create a new `HashFile` in the given path, which uses the given encoding for
+    /// converting the keys and values to (and from) the stuff in the files.
+    ///
+    /// ``` rust,no_run
+    /// # use catalog::HashFile;
+    /// # #[cfg(feature = "serde")]
+    /// # use catalog::Json;
+    /// # #[cfg(feature = "serde")]
+    /// # fn main() -> Result<(), catalog::Error> {
+    /// // with the `serde` feature
+    /// let mut hf: HashFile<(u8, String), Vec<u8>, _> =
+    ///     try!(HashFile::with_encoding("/tmp/SAMPLE", Json));
+    ///
+    /// try!(hf.insert((0, "foo".to_owned()), vec![1, 2, 3]));
+    /// # Ok(())
+    /// # }
+    /// # #[cfg(not(feature = "serde"))]
+    /// # fn main() {}
+    /// ```
+    ///
+    /// `HashFile::new` uses the [`Text`][text] encoding, which is based on the `Display`
+    /// and `FromStr` impls of the types. Note that the files don't know anything about the
+    /// encoding, and so it's up to the user to stick to the same encoding for a file.
+    ///
+    /// [text]: struct.Text.html
+    pub fn with_encoding(path: &str, encoding: E) -> Result<HashFile<K, V, E>, Error> {
+        HashFile::with_encoding_and_hasher(path, encoding, DefaultStableHasher::new())
+    }
+}
+
+impl<K: Display + FromStr, V: Display + FromStr, S: BuildHasher> HashFile<K, V, Text, S> {
+    /// Create a new `HashFile` in the given path, which uses the given `BuildHasher`
+    /// for hashing the keys.
+    ///
+    /// ``` rust,no_run
+    /// # use catalog::{DefaultStableHasher, HashFile};
+    /// # fn main() -> Result<(), catalog::Error> {
+    /// let hasher = DefaultStableHasher::with_keys(0xdead, 0xbeef);
+    /// let mut hf: HashFile<usize, String, _, _> =
+    ///     try!(HashFile::with_hasher("/tmp/SAMPLE", hasher));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// By default, `HashFile` uses the [`DefaultStableHasher`][hasher] (SipHash-2-4 with
+    /// zero keys), but a faster one can be used for trusted data (or a keyed one for
+    /// untrusted data). The hasher should produce the same hashes across runs (so, the
+    /// `RandomState` from the standard library won't work). A fingerprint of the hasher
+    /// is recorded in the file, and opening the file with some other hasher fails with
+    /// `Error::HasherMismatch`.
+    ///
+    /// [hasher]: struct.DefaultStableHasher.html
+    pub fn with_hasher(path: &str, hasher: S) -> Result<HashFile<K, V, Text, S>, Error> {
+        HashFile::with_encoding_and_hasher(path, Text, hasher)
+    }
+}
+
+impl<K, V, E, S> HashFile<K, V, E, S>
+    where E: Encoding<K> + Encoding<V> + Default, S: BuildHasher + Default
+{
+    /// Build a `HashFile` in the given path from the key/value pairs thrown by the iterator,
+    /// using (roughly) the given amount of memory (in bytes).
+    ///
+    /// ``` rust,no_run
+    /// # use catalog::HashFile;
+    /// # fn main() -> Result<(), catalog::Error> {
+    /// let pairs = (0..1000000).map(|i| (i, i * 2));
+    /// let mut hf: HashFile<usize, usize> =
+    ///     try!(HashFile::build_from_iter("/tmp/SAMPLE", pairs, 64 * 1024 * 1024));
+    ///
+    /// let value = try!(hf.get(&21));
+    /// assert_eq!(Some((42, 0)), value);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Instead of going through `insert` (and all the merges along the way), the pairs are
+    /// collected in memory, and whenever they exceed the budget, they're sorted and spilled
+    /// to a temp file. In the end, the chunks are merged (by the hashes of the keys), and the
+    /// "key" file and the (compacted) "data" file are written in one go. These two files are
+    /// exactly the same as the ones we'd get by inserting the pairs into an empty `HashFile`
+    /// and calling `finish` (so, a key that shows up more than once gets the last value,
+    /// and the others count as overwrites).
+    ///
+    /// The encoding and the hasher are the defaults of their types, and so are the other
+    /// settings (so, there's no bloom filter). If there's already a `HashFile` in the path,
+    /// then it's replaced.
+    pub fn build_from_iter<I>(path: &str, iter: I, memory_budget: usize)
+                              -> Result<HashFile<K, V, E, S>, Error>
+        where I: IntoIterator<Item=(K, V)>
+    {
+        let mut hash_file = try!(HashFile::with_encoding_and_hasher(path, E::default(),
+                                                                    S::default()));
+        try!(hash_file.build(iter, memory_budget));
+        Ok(hash_file)
+    }
+}
+
+impl<K, V, E: Encoding<K> + Encoding<V>, S: BuildHasher> HashFile<K, V, E, S> {
+    /// Create a new `HashFile` in the given path, with both the encoding and the hasher
+    /// (see [`with_encoding`][encoding] and [`with_hasher`][hasher]).
+    ///
+    /// [encoding]: #method.with_encoding
+    /// [hasher]: #method.with_hasher
+    pub fn with_encoding_and_hasher(path: &str, encoding: E, hasher: S)
+                                    -> Result<HashFile<K, V, E, S>, Error> {
+        // only one of us should be writing at a time (and no one should be reading, since
+        // the files could change under their feet)
+        let lock = try!(lock(path, true));
+
+        // if we went down in the middle of a commit, then it should go through first
+        // (and the temp files of the other writes should go away)
+        let recovery = try!(recover_locked(path));
+
+        // check the headers before we go ahead and create the "data" file
+        let mut base = try!(Run::open(path, &hasher));
+        try!(load_bloom(path, &mut base, &hasher));
+        let mut runs = vec![];
+        let mut next_run = 0;
+        for (seq, run_path) in try!(find_runs(path)) {
+            runs.push(try!(Run::open(&run_path, &hasher)));
+            next_run = seq + 1;
+        }
+
         let data_path = format!("{}{}", path, DAT_SUFFIX);
+        let data_file = try!(create_or_open_file(&data_path));
 
-        Ok(HashFile {
+        let mut hash_file = HashFile {
             hashed: BTreeMap::new(),
             capacity: 0,
-            line_length: match file_size > 0 {
-                true => {
-                    let line = try!(read_one_line(&mut file));
-                    line.len()
-                },
-                false => 0,
-            },
-            file: {
-                try!(seek_from_start(&mut file, 0));
-                file
-            },
-            data_file: try!(create_or_open_file(&data_path)),
+            bloom_fp_rate: None,
+            search_mode: SearchMode::Binary,
+            fence_every: 0,
+            cache: None,
+            log: None,
+            recovery: recovery,
+            _lock: lock,
+            base: base,
+            runs: runs,
+            next_run: next_run,
+            // new values should go after the ones we already have
+            data_idx: try!(get_size(&data_file)),
+            data_file: data_file,
+            data_reader: try!(DataReader::open(&data_path)),
             data_path: data_path,
-            data_idx: 0,
             path: path.to_owned(),
-            size: file_size,
-        })
+            encoding: encoding,
+            hasher: hasher,
+            _marker: PhantomData,
+        };
+
+        // put back the stuff we had in memory (if we went down before flushing it)
+        for entry in try!(read_log(&format!("{}{}", path, WAL_SUFFIX))) {
+            hash_file.put((entry.hash, entry.key), entry.value);
+            hash_file.recovery.log_entries += 1;
+        }
+
+        Ok(hash_file)
     }
 
     /// Set the capacity of the `HashFile` (to flush to the file whenever it exceeds this value).
@@ -295,25 +501,224 @@ impl<K: Display + FromStr + Hash, V: Display + FromStr> HashFile<K, V> {
     ///
     /// Note that `HashFile` flushes for every insertion by default, which is pretty inefficient.
     /// Hence, setting a proper capacity (depending on the usage) is necessary.
-    pub fn set_capacity(mut self, capacity: usize) -> HashFile<K, V> {
+    pub fn set_capacity(mut self, capacity: usize) -> HashFile<K, V, E, S> {
         self.capacity = capacity;
         self
     }
 
-    fn rename_temp_file(&mut self, rename_dat: bool) -> Result<(), String> {
-        if rename_dat {
-            try!(fs::rename(format!("{}{}", &self.data_path, TEMP_SUFFIX), &self.data_path)
-                    .map_err(|e| format!("Cannot rename the temp data file! ({})", e.description())));
-            self.data_file = try!(create_or_open_file(&self.data_path));
+    /// Write a bloom filter (with the given false positive rate) for the keys in the main
+    /// "key" file, whenever it's rewritten (i.e., by `finish`, `compact` and the merges).
+    ///
+    /// ``` rust,no_run
+    /// # use catalog::HashFile;
+    /// # fn main() -> Result<(), catalog::Error> {
+    /// let mut hf = try!(HashFile::new("/tmp/SAMPLE").map(|hf| hf.set_bloom_filter(0.01)));
+    /// try!(hf.insert(0, "foo".to_owned()));
+    /// try!(hf.finish());
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// The filter's written to a `.bloom` file alongside the others (`SAMPLE.bloom` in this
+    /// case), and it's loaded whenever the `HashFile` is opened. Lookups check the filter
+    /// before searching the main file, so most of the keys that aren't there are turned away
+    /// without touching the disk. A lower rate means fewer of the missing keys slip through,
+    /// but a bigger filter (about 10 bits per key for 1%, and 5 more for every tenth of that).
+    ///
+    /// Note that this should be set every time the file is opened - otherwise, the filter
+    /// is removed when the main file is rewritten (since it won't know about the new keys).
+    pub fn set_bloom_filter(mut self, fp_rate: f64) -> HashFile<K, V, E, S> {
+        self.bloom_fp_rate = Some(fp_rate);
+        self
+    }
+
+    /// Set the way we search for the keys in the files (binary search, by default).
+    ///
+    /// ``` rust,no_run
+    /// # use catalog::{HashFile, SearchMode};
+    /// # fn main() -> Result<(), catalog::Error> {
+    /// let mut hf: HashFile<usize, String> =
+    ///     try!(HashFile::new("/tmp/SAMPLE")
+    ///                   .map(|hf| hf.set_search_mode(SearchMode::Interpolation)));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// The [`Interpolation`][interpolation] mode makes the most sense for big files on
+    /// spinning disks (or network filesystems), where each seek costs a lot. It relies
+    /// on the hashes being uniform, which is true for `DefaultStableHasher` (and any
+    /// decent hasher).
+    ///
+    /// [interpolation]: enum.SearchMode.html#variant.Interpolation
+    pub fn set_search_mode(mut self, mode: SearchMode) -> HashFile<K, V, E, S> {
+        self.search_mode = mode;
+        self
+    }
+
+    /// Keep the hashes of every `every`-th row of the "key" files in memory, so that the
+    /// lookups know the block of rows to look into (before they touch the files).
+    ///
+    /// ``` rust,no_run
+    /// # use catalog::HashFile;
+    /// # fn main() -> Result<(), catalog::Error> {
+    /// let mut hf: HashFile<usize, String> =
+    ///     try!(HashFile::new("/tmp/SAMPLE").and_then(|hf| hf.set_fence_index(64)));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Each fence takes 8 bytes, so this takes about `8 * n / every` bytes for `n` rows.
+    /// A smaller value means more memory, but fewer reads for each lookup (the search
+    /// has `log2(every)` steps left, all of which are in the same block). Unlike the
+    /// other settings, this reads the files right away (and so, it returns a `Result`).
+    /// The fences are kept up to date as the runs are written and merged. Setting this
+    /// to zero drops them.
+    pub fn set_fence_index(mut self, every: u64) -> Result<HashFile<K, V, E, S>, Error> {
+        self.fence_every = every;
+        for run in iter::once(&mut self.base).chain(self.runs.iter_mut()) {
+            try!(run.load_fences(every));
+        }
+
+        Ok(self)
+    }
+
+    /// Cache the stuff we read from the files (the blocks of the "key" files, and the records
+    /// from the "data" file), using (roughly) the given amount of memory (in bytes).
+    ///
+    /// ``` rust,no_run
+    /// # use catalog::HashFile;
+    /// # fn main() -> Result<(), catalog::Error> {
+    /// let mut hf: HashFile<usize, String> =
+    ///     try!(HashFile::new("/tmp/SAMPLE").map(|hf| hf.set_cache_size(16 * 1024 * 1024)));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// The least recently used stuff is thrown out to make room for the new stuff, so
+    /// this pays off when some of the keys are looked up way more often than the others
+    /// (see [`cache_stats`][stats] for how well it's doing). With the `mmap` feature,
+    /// the reads go through the mapped bytes instead (which are already cached by the OS),
+    /// and only the stuff written after the files were mapped is cached. Setting this
+    /// to zero drops the cache.
+    ///
+    /// [stats]: #method.cache_stats
+    pub fn set_cache_size(mut self, bytes: usize) -> HashFile<K, V, E, S> {
+        self.cache = match bytes {
+            0 => None,
+            _ => Some(Arc::new(BlockCache::new(bytes))),
+        };
+
+        for run in iter::once(&mut self.base).chain(self.runs.iter_mut()) {
+            run.set_cache(self.cache.clone());
         }
 
-        try!(fs::rename(format!("{}{}", &self.path, TEMP_SUFFIX), &self.path)
-                .map_err(|e| format!("Cannot rename the temp file! ({})", e.description())));
-        self.file = try!(create_or_open_file(&self.path));
-        self.size = try!(get_size(&self.file));
+        self.data_reader.set_cache(self.cache.clone());
+        self
+    }
+
+    /// Get the number of hits and misses (and the bytes in use) of the cache, since it was
+    /// set up with [`set_cache_size`][cache-size]. It's all zeros if we don't have a cache.
+    ///
+    /// ``` rust,no_run
+    /// # use catalog::HashFile;
+    /// # fn main() -> Result<(), catalog::Error> {
+    /// # let hf: HashFile<usize, String> = try!(HashFile::new("/tmp/SAMPLE"));
+    /// let stats = hf.cache_stats();
+    /// println!("hit rate: {}", stats.hits as f64 / (stats.hits + stats.misses) as f64);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [cache-size]: #method.set_cache_size
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.as_ref().map(|c| c.stats()).unwrap_or_default()
+    }
+
+    /// Get the new run ready for the lookups (with its fences and the cache, if we're
+    /// using them).
+    fn prepare(&self, run: &mut Run) -> Result<(), Error> {
+        // the fences are loaded first (we don't want them all over the cache)
+        try!(run.load_fences(self.fence_every));
+        run.set_cache(self.cache.clone());
         Ok(())
     }
 
+    /// Open a reader for the "data" file (through the cache, if we're using one).
+    fn open_data_reader(&self) -> Result<DataReader, Error> {
+        let mut reader = try!(DataReader::open(&self.data_path));
+        reader.set_cache(self.cache.clone());
+        Ok(reader)
+    }
+
+    /// Write the stuff we insert (or remove) to a write-ahead log (`SAMPLE.wal`, in this
+    /// case) before it goes into memory, so that it isn't lost if we go down before it's
+    /// flushed to the files.
+    ///
+    /// ``` rust,no_run
+    /// # use catalog::HashFile;
+    /// # fn main() -> Result<(), catalog::Error> {
+    /// let mut hf: HashFile<usize, String> =
+    ///     try!(HashFile::new("/tmp/SAMPLE")
+    ///                   .map(|hf| hf.set_capacity(1000000))
+    ///                   .and_then(|hf| hf.set_write_ahead_log(true)));
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// `HashFile::new` always puts back the stuff from the log (if there's one) into memory,
+    /// and the log is removed once the stuff is flushed (along with the new run, so that
+    /// it can't be put back twice). Each entry is written to the file right away, so it
+    /// survives the process getting killed, but it isn't synced to the disk (so, a power cut
+    /// could still lose the last few of them). Like the fences, this opens the log right
+    /// away (and so, it returns a `Result`).
+    pub fn set_write_ahead_log(mut self, enabled: bool) -> Result<HashFile<K, V, E, S>, Error> {
+        self.log = match enabled {
+            true => Some(try!(open_log(&self.log_path()))),
+            false => None,
+        };
+
+        Ok(self)
+    }
+
+    /// Get the stuff that was recovered while opening the file (if we went down while
+    /// writing to it earlier).
+    ///
+    /// ``` rust,no_run
+    /// # use catalog::HashFile;
+    /// # fn main() -> Result<(), catalog::Error> {
+    /// let hf: HashFile<usize, String> = try!(HashFile::new("/tmp/SAMPLE"));
+    /// if !hf.recovery().is_clean() {
+    ///     println!("recovered from a crash: {:?}", hf.recovery());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// See [`recover`][recover] for the details.
+    ///
+    /// [recover]: fn.recover.html
+    pub fn recovery(&self) -> &Recovery {
+        &self.recovery
+    }
+
+    fn log_path(&self) -> String {
+        format!("{}{}", self.path, WAL_SUFFIX)
+    }
+
+    /// Encode (and escape) the thing, so that it can be written to the file.
+    fn encode<T>(&self, thing: &T) -> Result<String, Error>
+        where E: Encoding<T>
+    {
+        self.encoding.encode(thing).map(|s| escape(&s))
+    }
+
+    /// Get the hash of the key (which is computed from its encoded form), along with
+    /// the encoded (and escaped) key.
+    fn hash_key(&self, key: &K) -> Result<(u64, String), Error> {
+        let encoded = try!(self.encoding.encode(key));
+        Ok((hash(&self.hasher, encoded.as_bytes()), escape(&encoded)))
+    }
+
     /// Run this finally to flush the values (if any) from the struct to the file.
     ///
     /// ``` rust
@@ -326,111 +731,360 @@ impl<K: Display + FromStr + Hash, V: Display + FromStr> HashFile<K, V> {
     /// try!(hf.finish());
     /// ```
     ///
-    /// This method is essential, because the "key" file would otherwise be invalid. This
-    /// method ensures a constant line length throughout the file by properly padding them
-    /// whenever required. It also gets rid of the unnecessary values from the "data" file.
-    pub fn finish(&mut self) -> Result<(), String> {
+    /// This flushes the stuff we have in memory, merges all the runs into the main file
+    /// (dropping the tombstones along the way), and gets rid of the unnecessary values
+    /// from the "data" file. Once this is done, we're left with just the two files
+    /// (and the `.bloom` file, if we've asked for it with `set_bloom_filter`).
+    pub fn finish(&mut self) -> Result<(), Error> {
         if self.hashed.len() > 0 {
             try!(self.flush_map());
         }
 
-        // We need to traverse one last time to confirm that each row has the same length
-        // (and of course, remove the *removed* values from the data file)
+        self.merge(0, true)
+    }
+
+    /// Merge all the runs into the main "key" file.
+    ///
+    /// ``` rust,no_run
+    /// # use catalog::HashFile;
+    /// # fn main() -> Result<(), catalog::Error> {
+    /// let mut hf = try!(HashFile::new("/tmp/SAMPLE").map(|hf| hf.set_capacity(100)));
+    ///
+    /// for i in 0..1000 {
+    ///     try!(hf.insert(i, i * 2));
+    /// }
+    ///
+    /// try!(hf.compact());
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// The runs are merged on their own (whenever a run grows as big as the one before it),
+    /// but this is handy when we're gonna do a lot of lookups, since `get` has to search
+    /// through each run. Unlike `finish`, this doesn't flush the stuff we have in memory,
+    /// and it doesn't clean up the "data" file.
+    pub fn compact(&mut self) -> Result<(), Error> {
+        if self.runs.is_empty() {
+            return Ok(())
+        }
+
+        self.merge(0, false)
+    }
+
+    /// Sort the pairs from the iterator (spilling them to temp files as required), and
+    /// replace the files with the result (see `build_from_iter`).
+    fn build<I>(&mut self, iter: I, memory_budget: usize) -> Result<(), Error>
+        where I: IntoIterator<Item=(K, V)>
+    {
+        let mut chunk: BTreeMap<(u64, String), (usize, String)> = BTreeMap::new();
+        let mut chunk_size = 0;
+        let mut spills = vec![];
+        let mut pairs = 0;
+
+        for (key, value) in iter {
+            pairs += 1;
+            let hashed = try!(self.hash_key(&key));
+            let value = try!(self.encode(&value));
+            chunk_size += mem::size_of::<((u64, String), (usize, String))>() +
+                          hashed.1.len() + value.len();
+
+            match chunk.entry(hashed) {
+                // a key that shows up again counts as an overwrite
+                Entry::Occupied(mut e) => {
+                    let count = e.get().0 + 1;
+                    e.insert((count, value));
+                },
+                Entry::Vacant(e) => {
+                    e.insert((0, value));
+                },
+            }
+
+            if chunk_size > memory_budget {
+                let spill_path = format!("{}{}{}", self.path, SPILL_SUFFIX, spills.len());
+                try!(write_spill(&spill_path, mem::take(&mut chunk)));
+                spills.push(spill_path);
+                chunk_size = 0;
+            }
+        }
+
+        // the chunks go from the oldest to the newest (the last one's still in memory)
+        let mut sources: Vec<SpillSource> = vec![];
+        for spill_path in &spills {
+            sources.push(Box::new(try!(SpillReader::open(spill_path))));
+        }
+
+        sources.push(Box::new(chunk.into_iter().map(|((hash, key), (count, value))| {
+            Ok(SpillEntry {
+                hash: hash,
+                key: key,
+                count: count,
+                value: value,
+            })
+        })));
+
+        let mut heap = BinaryHeap::new();
+        for source in 0..sources.len() {
+            try!(push_next(&mut heap, &mut sources, source));
+        }
+
+        let data_temp_path = format!("{}{}", &self.data_path, TEMP_SUFFIX);
+        let mut data_file = try!(File::create(&data_temp_path));
+        let mut writer = try!(RunWriter::new(&self.path));
+        let mut data_idx = 0;
+        let mut bloom = self.bloom_fp_rate.map(|p| BloomFilter::new(pairs, p));
 
         {
-            let buf_reader = BufReader::new(&mut self.file);
-            let mut out_file = try!(create_or_open_file(&format!("{}{}", &self.path, TEMP_SUFFIX)));
-            let mut buf_writer = BufWriter::new(&mut out_file);
-            let mut data_file = try!(create_or_open_file(&format!("{}{}", &self.data_path, TEMP_SUFFIX)));
             let mut data_writer = BufWriter::new(&mut data_file);
-            self.data_idx = 0;
-
-            for ref line in buf_reader.lines().filter_map(|l| l.ok()) {
-                let mut key_index = try!(line.parse::<KeyIndex<K>>());
-                try!(seek_from_start(&mut self.data_file, key_index.idx));
-                let value = try!(read_one_line(&mut self.data_file));
-                key_index.idx = self.data_idx;
-                self.data_idx += try!(write_buffer(&mut data_writer, &value, &mut 0));
-                // Even though this takes a mutable reference, we can be certain that
-                // we've found the maximum row length for this session
-                try!(write_buffer(&mut buf_writer, &key_index.to_string(), &mut self.line_length));
+            // the heap throws the entries in ascending order (and in case of the same key,
+            // from the oldest chunk to the newest)
+            while let Some(Reverse((hash, key, source, mut count, mut value))) = heap.pop() {
+                try!(push_next(&mut heap, &mut sources, source));
+                loop {
+                    match heap.peek() {
+                        Some(&Reverse((h, ref k, _, _, _))) if (h, k) == (hash, &key) => (),
+                        _ => break,
+                    }
+
+                    let Reverse((_, _, source, c, v)) = heap.pop().unwrap();
+                    try!(push_next(&mut heap, &mut sources, source));
+                    count += 1 + c;
+                    value = v;
+                }
+
+                if let Some(ref mut bloom) = bloom {
+                    bloom.insert(hash);
+                }
+
+                let record = record(&key, Some(&value));
+                let mut key_idx = KeyIndex::new(key);
+                key_idx.idx = data_idx;
+                key_idx.count = count;
+                data_idx += try!(write_buffer(&mut data_writer, &record));
+                try!(writer.push(hash, &key_idx));
             }
         }
 
-        self.rename_temp_file(true)
+        mem::drop(sources);
+        try!(data_file.sync_all());
+        let rows = writer.rows();
+        let temp_path = try!(writer.finish(&self.hasher, FLAG_FINISHED));
+
+        let mut commit = Commit::new();
+        commit.rename(&data_temp_path, &self.data_path);
+        commit.rename(&temp_path, &self.path);
+        try!(self.stage_bloom(bloom.as_ref(), rows, &mut commit));
+        for run in self.runs.drain(..) {
+            commit.remove(&run.path);
+        }
+
+        // whatever we had in memory (or in the log) is replaced too
+        self.hashed.clear();
+        commit.remove(&self.log_path());
+
+        try!(commit.apply(&self.path));
+        if self.log.is_some() {
+            self.log = Some(try!(open_log(&self.log_path())));
+        }
+
+        let mut base = try!(Run::open(&self.path, &self.hasher));
+        try!(self.prepare(&mut base));
+        base.bloom = bloom;
+        self.base = base;
+        self.data_file = try!(create_or_open_file(&self.data_path));
+        self.data_reader = try!(self.open_data_reader());
+        self.data_idx = data_idx;
+
+        for spill_path in spills {
+            try!(fs::remove_file(&spill_path));
+        }
+
+        Ok(())
+    }
+
+    fn run_path(&self, seq: u64) -> String {
+        format!("{}{}{}", self.path, RUN_SUFFIX, seq)
+    }
+
+    /// Write the bloom filter for the (newly written) main file with the given rows, and add
+    /// it to the commit. If we don't have a filter, then the old one is removed (since
+    /// it doesn't know about the new keys).
+    fn stage_bloom(&self, bloom: Option<&BloomFilter>, rows: u64, commit: &mut Commit)
+                   -> Result<(), Error> {
+        let bloom_path = format!("{}{}", self.path, BLOOM_SUFFIX);
+        match bloom {
+            Some(bloom) => {
+                let temp_path = format!("{}{}", bloom_path, TEMP_SUFFIX);
+                try!(bloom.write_to(&temp_path, rows, hash(&self.hasher, HASHER_PROBE)));
+                commit.rename(&temp_path, &bloom_path);
+            },
+            None => commit.remove(&bloom_path),
+        }
+
+        Ok(())
     }
 
-    fn flush_map(&mut self) -> Result<(), String> {
+    /// Merge the runs starting from the given position (where the main file is at `0`,
+    /// followed by the runs from the oldest to the newest) into one. If the main file is
+    /// involved, then the tombstones are dropped (as there's nothing older for them to hide),
+    /// and if `compact_data` is set, then the values are copied to a new "data" file.
+    fn merge(&mut self, from: usize, compact_data: bool) -> Result<(), Error> {
+        let data_temp_path = format!("{}{}", &self.data_path, TEMP_SUFFIX);
+        let mut data_idx = 0;
+        let mut commit = Commit::new();
+
+        let (merged_path, bloom) = {
+            let runs = iter::once(&self.base).chain(self.runs.iter()).skip(from)
+                                             .collect::<Vec<_>>();
+            let mut inputs = vec![];
+            for run in &runs {
+                inputs.push(try!(run.entries()));
+            }
+
+            // the merged rows can't be more than this (so, the filter will do)
+            let total_rows = runs.iter().map(|r| r.rows).sum();
+            let mut bloom = match from {
+                0 => self.bloom_fp_rate.map(|p| BloomFilter::new(total_rows, p)),
+                _ => None,
+            };
+
+            let mut writer = try!(RunWriter::new(&runs[0].path));
+            let mut data_file = match compact_data {
+                true => Some(try!(File::create(&data_temp_path))),
+                false => None,
+            };
+
+            {
+                let mut data_writer = data_file.as_mut().map(BufWriter::new);
+
+                for merged in Merged::new(inputs, &self.data_reader) {
+                    let (hash, mut key_idx) = try!(merged);
+                    if from == 0 {
+                        if key_idx.removed {
+                            continue        // drop the tombstone (along with its value)
+                        }
+
+                        key_idx.revived = false;    // there's nothing older to hide
+                    }
+
+                    if let Some(ref mut bloom) = bloom {
+                        bloom.insert(hash);
+                    }
+
+                    // (the records are checked, and copied along with their checksums)
+                    if let Some(ref mut data_writer) = data_writer {
+                        let record = try!(self.data_reader.line_at(key_idx.idx));
+                        key_idx.idx = data_idx;
+                        data_idx += try!(write_buffer(data_writer, &record));
+                    }
+
+                    try!(writer.push(hash, &key_idx));
+                }
+            }
+
+            if let Some(ref file) = data_file {
+                try!(file.sync_all());
+                commit.rename(&data_temp_path, &self.data_path);
+            }
+
+            let rows = writer.rows();
+            let temp_path = try!(writer.finish(&self.hasher,
+                                               if compact_data { FLAG_FINISHED } else { 0 }));
+            commit.rename(&temp_path, &runs[0].path);
+            if from == 0 {
+                try!(self.stage_bloom(bloom.as_ref(), rows, &mut commit));
+            }
+
+            // the newer runs have been merged into this one
+            for run in &runs[1..] {
+                commit.remove(&run.path);
+            }
+
+            (runs[0].path.clone(), bloom)
+        };
+
+        try!(commit.apply(&self.path));
+        let mut merged = try!(Run::open(&merged_path, &self.hasher));
+        try!(self.prepare(&mut merged));
+        merged.bloom = bloom;       // (only for the main file)
+
+        if compact_data {
+            self.data_file = try!(create_or_open_file(&self.data_path));
+            self.data_reader = try!(self.open_data_reader());
+            self.data_idx = data_idx;
+        }
+
+        self.runs.truncate(from);
+        match from {
+            0 => self.base = merged,
+            _ => self.runs[from - 1] = merged,
+        }
+
+        Ok(())
+    }
+
+    /// Flush the stuff we have in memory to a new run (and merge the runs, if needed).
+    fn flush_map(&mut self) -> Result<(), Error> {
         let map = mem::replace(&mut self.hashed, BTreeMap::new());
 
+        // seeking, so that we can ensure that the cursor is at the end while writing.
+        try!(seek_from_start(&mut self.data_file, self.data_idx));
+        let mut rows = Vec::with_capacity(map.len());
+
         {
-            // seeking, so that we can ensure that the cursor is at the start while reading,
-            // and at the end while writing.
-            try!(seek_from_start(&mut self.file, 0));
-            try!(seek_from_start(&mut self.data_file, self.data_idx));
-
-            let buf_reader = BufReader::new(&mut self.file);
-            let mut out_file = try!(create_or_open_file(&format!("{}{}", &self.path, TEMP_SUFFIX)));
-            let mut buf_writer = BufWriter::new(&mut out_file);
             let mut data_writer = BufWriter::new(&mut self.data_file);
+            // the map throws the keys in ascending order
+            for ((hash, key), (mut key_idx, val)) in map {
+                // (tombstones don't have any values, but they need their keys)
+                let record = record(&key, val.as_deref());
+                key_idx.idx = self.data_idx;
+                self.data_idx += try!(write_buffer(&mut data_writer, &record));
+                rows.push((hash, key_idx));
+            }
+        }
 
-            // both the iterators throw the values in ascending order
-            let mut file_iter = buf_reader.lines().filter_map(|l| l.ok()).peekable();
-            let mut map_iter = map.into_iter().peekable();
-
-            loop {
-                let compare_result = match (file_iter.peek(), map_iter.peek()) {
-                    (Some(file_line), Some(&(btree_key_hash, _))) => {
-                        let key = file_line.split(SEP).next().unwrap();
-                        let file_key_hash = match key.parse::<K>() {
-                            Ok(k_v) => hash(&k_v),
-                            Err(_) => {
-                                // skip the line if we find any errors
-                                // (we don't wanna stop the giant build because of this error)
-                                continue
-                            },
-                        };
-
-                        file_key_hash.cmp(&btree_key_hash)
-                    },
-                    (Some(_), None) => Ordering::Less,
-                    (None, Some(_)) => Ordering::Greater,
-                    (None, None) => break,
-                };
-
-                match compare_result {
-                    Ordering::Equal => {
-                        let file_line = file_iter.next().unwrap();
-                        let (_, (mut btree_key, val)) = map_iter.next().unwrap();
-                        btree_key.idx = self.data_idx;
-                        self.data_idx += try!(write_buffer(&mut data_writer, &val.to_string(), &mut 0));
-
-                        let mut file_key = match file_line.parse::<KeyIndex<K>>() {
-                            Ok(k_i) => k_i,
-                            Err(_) => continue,     // skip on error
-                        };
-
-                        file_key += btree_key;
-                        try!(write_buffer(&mut buf_writer, &file_key.to_string(),
-                                          &mut self.line_length));
-                    },
-                    Ordering::Less => {
-                        try!(write_buffer(&mut buf_writer, &file_iter.next().unwrap(),
-                                          &mut self.line_length));
-                    },
-                    Ordering::Greater => {
-                        let (_, (mut btree_key, val)) = map_iter.next().unwrap();
-                        btree_key.idx = self.data_idx;
-                        self.data_idx += try!(write_buffer(&mut data_writer, &val.to_string(), &mut 0));
+        // the records should be on the disk before the rows that point to them
+        try!(self.data_file.sync_data());
 
-                        try!(write_buffer(&mut buf_writer, &(btree_key.to_string()),
-                                          &mut self.line_length));
-                    },
-                }
+        // so that the new records can be read from the mapped bytes (if we're using them)
+        try!(self.data_reader.remap());
+
+        let run_path = self.run_path(self.next_run);
+        let mut writer = try!(RunWriter::new(&run_path));
+        for &(hash, ref key_idx) in &rows {
+            try!(writer.push(hash, key_idx));
+        }
+
+        // the log goes away along with the new run (or neither of them do)
+        let mut commit = Commit::new();
+        commit.rename(&try!(writer.finish(&self.hasher, 0)), &run_path);
+        if self.log.is_some() || Path::new(&self.log_path()).exists() {
+            commit.remove(&self.log_path());
+        }
+
+        try!(commit.apply(&self.path));
+        if self.log.is_some() {
+            self.log = Some(try!(open_log(&self.log_path())));
+        }
+
+        let mut run = try!(Run::open(&run_path, &self.hasher));
+        try!(self.prepare(&mut run));
+        self.runs.push(run);
+        self.next_run += 1;
+
+        // Keep merging the newest run into the one before it (which could be the main file),
+        // as long as the older one isn't any bigger. Like a binary counter, this ensures that
+        // we have O(log-n) runs, and that each row is rewritten O(log-n) times.
+        while let Some(newest) = self.runs.last().map(|r| r.rows) {
+            let n = self.runs.len();
+            let older = if n > 1 { self.runs[n - 2].rows } else { self.base.rows };
+            if older > newest {
+                break
             }
+
+            try!(self.merge(n - 1, false));
         }
 
-        self.rename_temp_file(false)
+        Ok(())
     }
 
     /// Insert a key/value pair into the map.
@@ -443,84 +1097,390 @@ impl<K: Display + FromStr + Hash, V: Display + FromStr> HashFile<K, V> {
     ///
     /// Note that this method flushes the stuff to the files once the map reaches the
     /// defined capacity.
-    pub fn insert(&mut self, key: K, value: V) -> Result<(), String> {
-        let mut key_idx = KeyIndex::new(key);
-        let hashed = hash(&key_idx);
+    pub fn insert(&mut self, key: K, value: V) -> Result<(), Error> {
+        let hashed = try!(self.hash_key(&key));
+        let value = try!(self.encode(&value));
+        try!(self.log(&hashed, Some(&value)));
+        // flush to file once the capacity is full
+        if self.put(hashed, Some(value)) && self.hashed.len() > self.capacity {
+            try!(self.flush_map());
+        }
+
+        Ok(())
+    }
+
+    /// Remove a key (along with its value) from the map.
+    ///
+    /// ``` rust,no_run
+    /// # use catalog::HashFile;
+    /// # fn main() -> Result<(), catalog::Error> {
+    /// let mut hf = try!(HashFile::new("/tmp/SAMPLE").map(|hf| hf.set_capacity(1000)));
+    /// try!(hf.insert(0, "foo".to_owned()));
+    /// try!(hf.finish());
+    ///
+    /// try!(hf.remove(&0));
+    /// try!(hf.finish());
+    ///
+    /// let value = try!(hf.get(&0));
+    /// assert_eq!(None, value);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// The key can't be removed from the file right away, because that'd mean rewriting
+    /// the whole thing. So, this only records a "tombstone" for the key, which gets written to
+    /// the file (like any other key) while flushing. Once it's there, `get` will ignore the key,
+    /// and it (along with its value) will be cleaned up once it's merged into the main file.
+    pub fn remove(&mut self, key: &K) -> Result<(), Error> {
+        let hashed = try!(self.hash_key(key));
+        try!(self.log(&hashed, None));
+        if self.put(hashed, None) && self.hashed.len() > self.capacity {
+            try!(self.flush_map());
+        }
+
+        Ok(())
+    }
+
+    /// Put the (encoded) value for the key into the map, or a tombstone if there's no value
+    /// (which overrides any value we have in the map). Returns whether the key is new
+    /// to the map.
+    fn put(&mut self, hashed: (u64, String), value: Option<String>) -> bool {
+        let value = match value {
+            Some(value) => value,
+            None => {
+                let key_idx = KeyIndex::tombstone(hashed.1.clone());
+                return self.hashed.insert(hashed, (key_idx, None)).is_none()
+            },
+        };
+
+        let mut key_idx = KeyIndex::new(hashed.1.clone());
         if let Some(key_val) = self.hashed.get_mut(&hashed) {
             *key_val = {
-                key_idx.count += 1;
-                (key_idx, value)
+                // count the overwrites while we're in memory (a removed key starts afresh,
+                // and it hides whatever we have in the files)
+                match key_val.1 {
+                    Some(_) => {
+                        key_idx.count = key_val.0.count + 1;
+                        key_idx.revived = key_val.0.revived;
+                    },
+                    None => key_idx.revived = true,
+                }
+
+                (key_idx, Some(value))
             };
 
-            return Ok(())
+            return false
         }
 
-        self.hashed.insert(hashed, (key_idx, value));
-        if self.hashed.len() > self.capacity {  // flush to file once the capacity is full
-            try!(self.flush_map());
-        }
+        self.hashed.insert(hashed, (key_idx, Some(value)));
+        true
+    }
 
-        Ok(())
+    /// Write the insertion (or the removal, if there's no value) to the write-ahead log
+    /// (if we're keeping one).
+    fn log(&mut self, hashed: &(u64, String), value: Option<&String>) -> Result<(), Error> {
+        match self.log {
+            Some(ref mut log) => append_log(log, hashed.0, &hashed.1, value.map(|v| &v[..])),
+            None => Ok(()),
+        }
     }
 
     /// Get the value corresponding to the key from the map.
     ///
-    /// ``` rust
+    /// ``` rust,no_run
+    /// # use catalog::HashFile;
+    /// # fn main() -> Result<(), catalog::Error> {
     /// let mut hf = try!(HashFile::new("/tmp/SAMPLE").map(|hf| hf.set_capacity(1000)));
-    /// try!(hf.insert(0, "foo"));
-    /// try!(hf.insert(1, "bar"));
-    /// try!(hf.finish());      // don't forget to finish!
+    /// try!(hf.insert(0, "foo".to_owned()));
+    /// try!(hf.insert(1, "bar".to_owned()));
+    /// try!(hf.finish());
     ///
     /// let value = try!(hf.get(&0));
-    /// assert_eq!(Some(("foo", 0)), value);
+    /// assert_eq!(Some(("foo".to_owned(), 0)), value);
+    ///
+    /// try!(hf.insert(0, "baz".to_owned()));  // this is still in memory...
+    /// let value = try!(hf.get(&0));          // ... but we can still get it
+    /// assert_eq!(Some(("baz".to_owned(), 1)), value);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// The files are read with positional reads (which don't move any cursors around), so
+    /// this only needs a shared reference (see [`HashFileReader`][reader] for sharing the
+    /// files among threads).
+    ///
+    /// With the `mmap` feature, the files are memory-mapped, and the binary search goes
+    /// through the mapped bytes instead (which saves a couple of syscalls and allocations
+    /// for each step). Note that the files shouldn't be modified by anyone else while
+    /// they're mapped.
+    ///
+    /// The stuff we have on hand (i.e., the ones which haven't been flushed yet) is checked
+    /// first, followed by the runs (from the newest to the oldest) and the main file. The
+    /// newest value wins, while the count is put together from all of them. Note that the
+    /// value in memory is obtained by decoding its encoded form (just like it'd be decoded
+    /// from the file), so that we get the same thing either way.
+    ///
+    /// [reader]: struct.HashFileReader.html
+    pub fn get(&self, key: &K) -> Result<Option<(V, usize)>, Error> {
+        let hashed_key = try!(self.hash_key(key));
+        let (newest, value) = match self.hashed.get(&hashed_key) {
+            Some(&(_, None)) => return Ok(None),    // removed, but not flushed yet
+            Some(&(ref key_idx, Some(ref value))) => (Some(key_idx.clone()), Some(value)),
+            None => (None, None),
+        };
+
+        let runs = self.runs.iter().rev().chain(iter::once(&self.base));
+        match try!(lookup(runs, &self.data_reader, self.search_mode,
+                          hashed_key.0, &hashed_key.1, newest)) {
+            Some(key_idx) => {
+                let count = key_idx.count;
+                self.read_value(key_idx, value).map(|v| Some((v, count)))
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Check whether the map has a value for the key. Unlike `get`, this doesn't decode
+    /// anything, but it still reads the record (for the key) of each row that has the same
+    /// hash as the key, to make sure that it's the same key.
+    ///
+    /// ``` rust,no_run
+    /// # use catalog::HashFile;
+    /// # fn main() -> Result<(), catalog::Error> {
+    /// let mut hf = try!(HashFile::new("/tmp/SAMPLE").map(|hf| hf.set_capacity(1000)));
+    /// try!(hf.insert(0, "foo".to_owned()));
+    /// try!(hf.finish());
+    ///
+    /// assert!(try!(hf.contains_key(&0)));
+    /// assert!(!try!(hf.contains_key(&1)));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn contains_key(&self, key: &K) -> Result<bool, Error> {
+        let hashed_key = try!(self.hash_key(key));
+        if let Some((_, val)) = self.hashed.get(&hashed_key) {
+            return Ok(val.is_some())
+        }
+
+        // the newest row decides whether the key's alive
+        for run in self.runs.iter().rev().chain(iter::once(&self.base)) {
+            let found = try!(run.find(&self.data_reader, self.search_mode,
+                                      hashed_key.0, &hashed_key.1));
+            if let Some(key_idx) = found {
+                return Ok(!key_idx.removed)
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Get the number of key/value pairs in the map (including the ones in memory).
+    ///
+    /// ``` rust,no_run
+    /// # use catalog::HashFile;
+    /// # fn main() -> Result<(), catalog::Error> {
+    /// let mut hf = try!(HashFile::new("/tmp/SAMPLE").map(|hf| hf.set_capacity(1000)));
+    /// for i in 0..10 {
+    ///     try!(hf.insert(i, "foo".to_owned()));
+    /// }
+    ///
+    /// try!(hf.finish());
+    /// try!(hf.remove(&0));
+    /// assert_eq!(9, try!(hf.len()));
+    /// # Ok(())
+    /// # }
     /// ```
     ///
-    /// Unlike the `get` methods of other maps, this takes a mutable reference, because it
-    /// needs read access to the underlying file descriptors, as it moves the cursor here and
-    /// there to read the key/value pairs.
-    ///
-    /// Also, note that this method only gets the stuff from the file. So, it's necessary
-    /// that we've already flushed whatever we have on hand (by calling `finish`).
-    pub fn get(&mut self, key: &K) -> Result<Option<(V, usize)>, String> {
-        let hashed_key = hash(key);
-        if self.size == 0 || try!(get_size(&self.data_file)) == 0 {
-            return Ok(None)
-        }
-
-        let row_length = (self.line_length + 1) as u64;
-        let mut low = 0;
-        let mut high = self.size;
-
-        // Binary search and file seeking to find the value(s)
-
-        while low <= high {
-            let mid = (low + high) / 2;
-            // place the cursor at the start of a line
-            let new_line_pos = mid - (mid + row_length) % row_length;
-            try!(seek_from_start(&mut self.file, new_line_pos));
-            let line = try!(read_one_line(&mut self.file));
-
-            // we'll only need the hash of the key
-            let mut split = line.split(SEP);
-            let key_str = split.next().unwrap();
-            let key = try!(key_str.parse::<K>()
-                                  .map_err(|_| format!("Cannot parse the key from file!")));
-            let hashed = hash(&key);
-
-            if hashed == hashed_key {
-                let key_index = try!(line.parse::<KeyIndex<K>>());
-                try!(seek_from_start(&mut self.data_file, key_index.idx));
-                let line = try!(read_one_line(&mut self.data_file));
-                let value = try!(line.parse::<V>()
-                                     .map_err(|_| format!("Cannot parse the value from file!")));
-                return Ok(Some((value, key_index.count)))
-            } else if hashed < hashed_key {
-                low = mid + 1;
-            } else {
-                high = mid - 1;
+    /// The number of rows in the main file comes from its header, so for a finished file
+    /// (with nothing in memory), that's all there is to it. Otherwise, the keys in the runs
+    /// (and in memory) could be new, or they could be overwriting (or removing) the ones in
+    /// the main file. So, the runs are read through, and each of their keys is looked up
+    /// in the main file (the keys are read from the "data" file, but the values are never
+    /// decoded).
+    pub fn len(&self) -> Result<usize, Error> {
+        let mut len = self.base.rows as usize;
+        let mut inputs = vec![];
+        for run in &self.runs {
+            inputs.push(try!(run.entries()));
+        }
+
+        inputs.push(self.pending_rows());
+        for merged in Merged::new(inputs, &self.data_reader) {
+            let (hashed_key, mut key_idx) = try!(merged);
+            let found = try!(self.base.find(&self.data_reader, self.search_mode, hashed_key,
+                                            try!(key_idx.load_key(&self.data_reader))));
+            let in_base = found.is_some_and(|k| !k.removed);
+            match (key_idx.removed, in_base) {
+                (false, false) => len += 1,
+                (true, true) => len -= 1,
+                _ => (),
             }
         }
 
-        Ok(None)
+        Ok(len)
+    }
+
+    /// Check whether the map is empty (see [`len`][len]).
+    ///
+    /// [len]: #method.len
+    pub fn is_empty(&self) -> Result<bool, Error> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// Iterate over all the key/value pairs (along with the number of times they've been
+    /// overwritten), in the order of the hashes of the keys.
+    ///
+    /// ``` rust,no_run
+    /// # use catalog::HashFile;
+    /// # fn main() -> Result<(), catalog::Error> {
+    /// let mut hf = try!(HashFile::new("/tmp/SAMPLE").map(|hf| hf.set_capacity(1000)));
+    /// try!(hf.insert(0, "foo".to_owned()));
+    /// try!(hf.insert(1, "bar".to_owned()));
+    /// try!(hf.finish());
+    ///
+    /// try!(hf.insert(0, "baz".to_owned()));  // this is still in memory...
+    /// for entry in try!(hf.iter()) {         // ... but we'll still see it
+    ///     let (key, value, count) = try!(entry);
+    ///     println!("{}: {} (overwritten {} times)", key, value, count);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// This streams through the "key" files (just like `finish` does), and the stuff we have
+    /// in memory is merged along the way, so we get the same stuff that `get` would give us.
+    /// The hash order is pretty much random, but it's the same every time (for the same
+    /// hasher). Each entry is a `Result`, since the keys and values are read (and decoded)
+    /// only when we get to them.
+    pub fn iter<'a>(&'a self)
+                    -> Result<impl Iterator<Item=Result<(K, V, usize), Error>> + 'a, Error> {
+        let rows = try!(self.rows());
+        Ok(rows.map(move |row| {
+            let (key_idx, value) = try!(row);
+            let count = key_idx.count;
+            let (key, value) = try!(self.read_entry(key_idx, value));
+            Ok((try!(self.decode_key(&key)), try!(self.decode_value(&value)), count))
+        }))
+    }
+
+    /// Iterate over all the keys, in the order of their hashes (see [`iter`][iter]). The keys
+    /// are read from the "data" file, but the values aren't decoded.
+    ///
+    /// [iter]: #method.iter
+    pub fn keys<'a>(&'a self) -> Result<impl Iterator<Item=Result<K, Error>> + 'a, Error> {
+        let rows = try!(self.rows());
+        Ok(rows.map(move |row| {
+            let (mut key_idx, _) = try!(row);
+            self.decode_key(try!(key_idx.load_key(&self.data_reader)))
+        }))
+    }
+
+    /// Iterate over all the values, in the order of the hashes of their keys
+    /// (see [`iter`][iter]).
+    ///
+    /// [iter]: #method.iter
+    pub fn values<'a>(&'a self) -> Result<impl Iterator<Item=Result<V, Error>> + 'a, Error> {
+        let rows = try!(self.rows());
+        Ok(rows.map(move |row| row.and_then(|(key_idx, value)| self.read_value(key_idx, value))))
+    }
+
+    /// Merge the rows from all the files (and the stuff in memory), and throw the rows of
+    /// the keys which are alive, along with their values if they're still in memory.
+    fn rows<'a>(&'a self) -> Result<AliveRows<'a>, Error> {
+        let mut inputs: Vec<Rows<'a>> = vec![];
+        for run in iter::once(&self.base).chain(self.runs.iter()) {
+            inputs.push(try!(run.entries()));
+        }
+
+        // the stuff in memory is newer than everything in the files
+        inputs.push(self.pending_rows());
+        let hashed = &self.hashed;
+        let merged = Merged::new(inputs, &self.data_reader);
+        Ok(Box::new(merged.filter(|row| row.as_ref().map_or(true, |(_, k)| !k.removed))
+                          .map(move |row| row.map(|(h, k)| {
+            // (if the row's been merged with the one in memory, then we've got its key)
+            let value = match (hashed.is_empty(), &k.key) {
+                (false, Some(key)) => {
+                    hashed.get(&(h, key.clone())).and_then(|(_, v)| v.as_ref())
+                },
+                _ => None,
+            };
+
+            (k, value)
+        }))))
+    }
+
+    /// The rows for the stuff we have in memory (in ascending order).
+    fn pending_rows<'a>(&'a self) -> Rows<'a> {
+        Box::new(self.hashed.iter().map(|(&(h, _), (key_idx, _))| Ok((h, key_idx.clone()))))
+    }
+
+    /// Decode the key from its escaped form (as it appears in the files).
+    fn decode_key(&self, key: &str) -> Result<K, Error> {
+        unescape(key).and_then(|k| Encoding::<K>::decode(&self.encoding, &k))
+                     .ok_or_else(|| Error::KeyParse(key.to_owned()))
+    }
+
+    /// Decode the value from its escaped form (as it appears in the files).
+    fn decode_value(&self, value: &str) -> Result<V, Error> {
+        unescape(value).and_then(|v| Encoding::<V>::decode(&self.encoding, &v))
+                       .ok_or_else(|| Error::ValueParse(value.to_owned()))
+    }
+
+    /// Get the value for the row (from memory if we've got it, or from the "data" file,
+    /// unless it's been read along with the key), and decode it.
+    fn read_value(&self, mut key_idx: KeyIndex, value: Option<&String>) -> Result<V, Error> {
+        match value {
+            Some(value) => self.decode_value(value),
+            None => self.decode_value(&try!(key_idx.take_value(&self.data_reader))),
+        }
+    }
+
+    /// Get the (escaped) key and the value for the row - from memory if we've got the value
+    /// (the rows in memory always have their keys), or from the "data" file otherwise (where
+    /// they're in the same record).
+    fn read_entry<'a>(&self, mut key_idx: KeyIndex, value: Option<&'a String>)
+                      -> Result<(String, Cow<'a, str>), Error> {
+        match value {
+            Some(value) => {
+                let key = try!(key_idx.load_key(&self.data_reader)).to_owned();
+                Ok((key, Cow::Borrowed(value.as_str())))
+            },
+            None => {
+                let key = try!(key_idx.load_key(&self.data_reader)).to_owned();
+                Ok((key, Cow::Owned(try!(key_idx.take_value(&self.data_reader)))))
+            },
+        }
+    }
+
+    /// Check the files against their checksums, and find out what's been damaged (if any).
+    ///
+    /// ``` rust,no_run
+    /// # use catalog::HashFile;
+    /// # fn main() -> Result<(), catalog::Error> {
+    /// let hf: HashFile<usize, String> = try!(HashFile::new("/tmp/SAMPLE"));
+    /// let verification = try!(hf.verify());
+    /// if !verification.is_ok() {
+    ///     println!("the files are damaged: {:?}", verification);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Each row (in the "key" files) and each record (in the "data" file) carries a CRC32C
+    /// of its own, and the header of each "key" file has one for the whole file. This reads
+    /// through all the files and reports the offsets of the rows and records whose checksums
+    /// don't match (along with the files whose checksums don't match). Note that `get` and
+    /// the merges (`finish` and `compact`) also check the rows and records they read (and
+    /// fail with `Error::ChecksumMismatch`), but they only get to the stuff they need. The
+    /// stuff in memory isn't checked.
+    pub fn verify(&self) -> Result<Verification, Error> {
+        let mut verification = Verification::default();
+        for run in iter::once(&self.base).chain(self.runs.iter()) {
+            try!(run.verify(&mut verification));
+        }
+
+        try!(verify_records(&self.data_path, &mut verification));
+        Ok(verification)
     }
 }
@@ -0,0 +1,200 @@
+use checksum::{Verification, verify_records};
+use commit::MANIFEST_SUFFIX;
+use data::DataReader;
+use encoding::{Encoding, Text};
+use hash_file::{DAT_SUFFIX, find_runs, load_bloom};
+use hasher::DefaultStableHasher;
+use helpers::{escape, hash, unescape};
+use lock::lock;
+use run::{Run, lookup};
+use search::SearchMode;
+
+use Error;
+
+use std::fmt::Display;
+use std::fs::File;
+use std::hash::BuildHasher;
+use std::iter;
+use std::marker::PhantomData;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A read-only handle to the files of a [`HashFile`][hash-file], which (unlike a `HashFile`)
+/// can be opened by a bunch of readers at once. It's `Send` and `Sync`, so it can be shared
+/// among threads (say, in an `Arc`) without a lock.
+///
+/// ``` rust,no_run
+/// # use catalog::HashFileReader;
+/// # fn main() -> Result<(), catalog::Error> {
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// let reader: HashFileReader<usize, String> = try!(HashFileReader::new("/tmp/SAMPLE"));
+/// let reader = Arc::new(reader);
+///
+/// let handles = (0..4).map(|i| {
+///     let reader = reader.clone();
+///     thread::spawn(move || reader.get(&i))
+/// }).collect::<Vec<_>>();
+///
+/// for handle in handles {
+///     assert!(try!(handle.join().unwrap()).is_some());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Instead of seeking through the files, it uses positional reads (or the mapped bytes,
+/// with the `mmap` feature), so that the lookups don't have to move any cursors around.
+/// It's meant to be opened on a finished file - it doesn't know anything about the stuff
+/// that a `HashFile` has in memory, and it only sees the runs that were there when it
+/// was opened. Since it never writes anything, it won't finish a commit that was cut
+/// short by a crash - it fails with `Error::NeedsRecovery` instead (opening the file
+/// with `HashFile::new`, or calling `recover`, takes care of that).
+///
+/// It holds a shared lock on the files for as long as it's around, so any number of
+/// readers (in this process or others) can be opened together, but opening a `HashFile`
+/// (or a reader, while there's a `HashFile`) fails with `Error::Locked`.
+///
+/// [hash-file]: struct.HashFile.html
+pub struct HashFileReader<K, V, E: Encoding<K> + Encoding<V> = Text,
+                          S: BuildHasher = DefaultStableHasher> {
+    base: Run,
+    runs: Vec<Run>,
+    data_reader: DataReader,
+    data_path: String,
+    search_mode: SearchMode,
+    _lock: Option<File>,    // (shared) lock, which goes away when we're dropped
+    encoding: E,
+    hasher: S,
+    // we don't own any keys or values (so, they shouldn't affect `Send` or `Sync`)
+    _marker: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K: Display + FromStr, V: Display + FromStr> HashFileReader<K, V> {
+    /// Open the `HashFile` in the given path for reading. Unlike `HashFile::new`, this
+    /// fails if the files don't exist.
+    pub fn new(path: &str) -> Result<HashFileReader<K, V>, Error> {
+        HashFileReader::with_encoding(path, Text)
+    }
+}
+
+impl<K, V, E: Encoding<K> + Encoding<V>> HashFileReader<K, V, E> {
+    /// Open the `HashFile` in the given path for reading, with the encoding it was
+    /// built with (see [`HashFile::with_encoding`][encoding]).
+    ///
+    /// [encoding]: struct.HashFile.html#method.with_encoding
+    pub fn with_encoding(path: &str, encoding: E) -> Result<HashFileReader<K, V, E>, Error> {
+        HashFileReader::with_encoding_and_hasher(path, encoding, DefaultStableHasher::new())
+    }
+}
+
+impl<K: Display + FromStr, V: Display + FromStr, S: BuildHasher> HashFileReader<K, V, Text, S> {
+    /// Open the `HashFile` in the given path for reading, with the hasher it was
+    /// built with (see [`HashFile::with_hasher`][hasher]).
+    ///
+    /// [hasher]: struct.HashFile.html#method.with_hasher
+    pub fn with_hasher(path: &str, hasher: S) -> Result<HashFileReader<K, V, Text, S>, Error> {
+        HashFileReader::with_encoding_and_hasher(path, Text, hasher)
+    }
+}
+
+impl<K, V, E: Encoding<K> + Encoding<V>, S: BuildHasher> HashFileReader<K, V, E, S> {
+    /// Open the `HashFile` in the given path for reading, with both the encoding and
+    /// the hasher it was built with.
+    pub fn with_encoding_and_hasher(path: &str, encoding: E, hasher: S)
+                                    -> Result<HashFileReader<K, V, E, S>, Error> {
+        let lock = try!(lock(path, false));
+        // the files could be anywhere in between the old and the new ones
+        if Path::new(&format!("{}{}", path, MANIFEST_SUFFIX)).exists() {
+            return Err(Error::NeedsRecovery(path.to_owned()))
+        }
+
+        let mut base = try!(Run::open_read_only(path, &hasher));
+        try!(load_bloom(path, &mut base, &hasher));
+        let mut runs = vec![];
+        for (_, run_path) in try!(find_runs(path)) {
+            runs.push(try!(Run::open_read_only(&run_path, &hasher)));
+        }
+
+        let data_path = format!("{}{}", path, DAT_SUFFIX);
+        let data_reader = try!(DataReader::open(&data_path));
+
+        Ok(HashFileReader {
+            base: base,
+            runs: runs,
+            data_reader: data_reader,
+            data_path: data_path,
+            search_mode: SearchMode::Binary,
+            _lock: lock,
+            encoding: encoding,
+            hasher: hasher,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Set the way we search for the keys in the files (see
+    /// [`HashFile::set_search_mode`][search-mode]).
+    ///
+    /// [search-mode]: struct.HashFile.html#method.set_search_mode
+    pub fn set_search_mode(mut self, mode: SearchMode) -> HashFileReader<K, V, E, S> {
+        self.search_mode = mode;
+        self
+    }
+
+    /// Keep the hashes of every `every`-th row in memory (see
+    /// [`HashFile::set_fence_index`][fence-index]).
+    ///
+    /// [fence-index]: struct.HashFile.html#method.set_fence_index
+    pub fn set_fence_index(mut self, every: u64) -> Result<HashFileReader<K, V, E, S>, Error> {
+        for run in iter::once(&mut self.base).chain(self.runs.iter_mut()) {
+            try!(run.load_fences(every));
+        }
+
+        Ok(self)
+    }
+
+    /// Get the value corresponding to the key (along with the number of times it's been
+    /// overwritten), just like [`HashFile::get`][get].
+    ///
+    /// ``` rust,no_run
+    /// # use catalog::HashFileReader;
+    /// # fn main() -> Result<(), catalog::Error> {
+    /// let reader: HashFileReader<usize, String> = try!(HashFileReader::new("/tmp/SAMPLE"));
+    /// let value = try!(reader.get(&0));
+    /// assert_eq!(Some(("Z".to_owned(), 1)), value);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [get]: struct.HashFile.html#method.get
+    pub fn get(&self, key: &K) -> Result<Option<(V, usize)>, Error> {
+        let encoded = try!(self.encoding.encode(key));
+        let hashed_key = hash(&self.hasher, encoded.as_bytes());
+        let runs = self.runs.iter().rev().chain(iter::once(&self.base));
+        let key = escape(&encoded);
+        let mut key_idx = match try!(lookup(runs, &self.data_reader, self.search_mode,
+                                            hashed_key, &key, None)) {
+            Some(key_idx) => key_idx,
+            None => return Ok(None),
+        };
+
+        let value = try!(key_idx.take_value(&self.data_reader));
+        unescape(&value).and_then(|v| Encoding::<V>::decode(&self.encoding, &v))
+                        .map(|v| Some((v, key_idx.count)))
+                        .ok_or(Error::ValueParse(value))
+    }
+
+    /// Check the files against their checksums (see [`HashFile::verify`][verify]).
+    ///
+    /// [verify]: struct.HashFile.html#method.verify
+    pub fn verify(&self) -> Result<Verification, Error> {
+        let mut verification = Verification::default();
+        for run in iter::once(&self.base).chain(self.runs.iter()) {
+            try!(run.verify(&mut verification));
+        }
+
+        try!(verify_records(&self.data_path, &mut verification));
+        Ok(verification)
+    }
+}
@@ -0,0 +1,42 @@
+use siphasher::sip::SipHasher;
+
+use std::hash::BuildHasher;
+
+/// The default `BuildHasher` used by [`HashFile`][hash-file], which builds a `SipHasher`
+/// (SipHash-2-4). Unlike the `RandomState` in the standard library, it's deterministic,
+/// and so the hashes stay the same across processes, machines and platforms.
+///
+/// By default, the hasher uses zero keys. If the keys come from an untrusted source,
+/// then it's a good idea to use some secret keys instead (with [`with_keys`][with-keys]).
+/// Either way, the same keys should be used for reading the file.
+///
+/// [hash-file]: struct.HashFile.html
+/// [with-keys]: #method.with_keys
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DefaultStableHasher {
+    k0: u64,
+    k1: u64,
+}
+
+impl DefaultStableHasher {
+    /// Create a hasher with zero keys.
+    pub fn new() -> DefaultStableHasher {
+        DefaultStableHasher::default()
+    }
+
+    /// Create a hasher with the given keys.
+    pub fn with_keys(k0: u64, k1: u64) -> DefaultStableHasher {
+        DefaultStableHasher {
+            k0: k0,
+            k1: k1,
+        }
+    }
+}
+
+impl BuildHasher for DefaultStableHasher {
+    type Hasher = SipHasher;
+
+    fn build_hasher(&self) -> SipHasher {
+        SipHasher::new_with_keys(self.k0, self.k1)
+    }
+}
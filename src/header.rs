@@ -0,0 +1,148 @@
+use Error;
+use crc32c::crc32c_append;
+use hasher::DefaultStableHasher;
+use helpers::hash;
+
+use std::fs::File;
+use std::hash::BuildHasher;
+use std::io::{ErrorKind, Read, Write};
+
+/// The magic bytes which mark the start of a "key" file
+pub const MAGIC: &'static [u8; 8] = b"CATALOG\x1a";
+/// The version of the on-disk format (bumped whenever the layout changes)
+pub const FORMAT_VERSION: u16 = 5;
+/// The length of the header in bytes (the rows start right after this)
+pub const HEADER_LEN: u64 = 40;
+
+/// The hash algorithm (SipHash-2-4 with zero keys, over the encoded bytes of the key).
+/// Note that `0` was used by the older versions, which hashed the keys through
+/// `std::hash::Hash` (which depends on the key's type and the platform).
+pub const HASH_SIP24: u8 = 1;
+/// Some other hash algorithm (i.e., a user-supplied `BuildHasher`, or SipHash with some keys)
+pub const HASH_CUSTOM: u8 = 0xff;
+
+/// The bytes hashed for the header's "hasher check", which lets us find out whether
+/// a file is being opened with the same hasher that was used to build it
+pub const HASHER_PROBE: &'static [u8] = b"catalog";
+
+/// Set once the file has been through `finish` (i.e., there are no tombstones in the file,
+/// and the "data" file only has the values referred by the rows)
+pub const FLAG_FINISHED: u8 = 1 << 0;
+
+/// The fixed-size header at the start of the "key" file. All the integers are
+/// stored in little-endian, and the layout goes like so,
+///
+/// | bytes    | field                               |
+/// |----------|-------------------------------------|
+/// | `0..8`   | magic (`CATALOG\x1a`)               |
+/// | `8..10`  | format version                      |
+/// | `10`     | hash algorithm                      |
+/// | `11`     | flags                               |
+/// | `12..16` | row width (in bytes)                |
+/// | `16..24` | number of rows                      |
+/// | `24..32` | hash of `HASHER_PROBE`              |
+/// | `32..36` | checksum of the file                |
+/// | `36..40` | (unused)                            |
+///
+/// The checksum is the CRC32C of the rows, followed by the first 32 bytes of the header
+/// (i.e., everything but the checksum itself).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header {
+    pub version: u16,
+    pub hash_algorithm: u8,
+    pub flags: u8,
+    pub row_width: u32,
+    pub entries: u64,
+    pub hasher_check: u64,
+    pub checksum: u32,
+}
+
+impl Header {
+    pub fn new(row_width: u32, entries: u64, flags: u8,
+               hash_algorithm: u8, hasher_check: u64) -> Header {
+        Header {
+            version: FORMAT_VERSION,
+            hash_algorithm: hash_algorithm,
+            flags: flags,
+            row_width: row_width,
+            entries: entries,
+            hasher_check: hasher_check,
+            checksum: 0,
+        }
+    }
+
+    /// Create a header which carries the fingerprint of the given hasher.
+    pub fn for_hasher<S: BuildHasher>(hasher: &S, row_width: u32,
+                                      entries: u64, flags: u8) -> Header {
+        let check = hash(hasher, HASHER_PROBE);
+        // this is only informational (it's the check that's actually used)
+        let algorithm = match check == hash(&DefaultStableHasher::new(), HASHER_PROBE) {
+            true => HASH_SIP24,
+            false => HASH_CUSTOM,
+        };
+
+        Header::new(row_width, entries, flags, algorithm, check)
+    }
+
+    /// Read the header from the start of the file (assuming that the cursor's already there),
+    /// and check whether we can actually deal with the file.
+    pub fn read_from(file: &mut File, path: &str) -> Result<Header, Error> {
+        let mut bytes = [0; HEADER_LEN as usize];
+        match file.read_exact(&mut bytes) {
+            Ok(_) => (),
+            Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => {
+                return Err(Error::InvalidHeader(path.to_owned()))
+            },
+            Err(e) => return Err(Error::Io(e)),
+        }
+
+        if bytes[..8] != MAGIC[..] {
+            return Err(Error::InvalidHeader(path.to_owned()))
+        }
+
+        let header = Header {
+            version: u16::from_le_bytes([bytes[8], bytes[9]]),
+            hash_algorithm: bytes[10],
+            flags: bytes[11],
+            row_width: u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]),
+            entries: u64::from_le_bytes([bytes[16], bytes[17], bytes[18], bytes[19],
+                                         bytes[20], bytes[21], bytes[22], bytes[23]]),
+            hasher_check: u64::from_le_bytes([bytes[24], bytes[25], bytes[26], bytes[27],
+                                              bytes[28], bytes[29], bytes[30], bytes[31]]),
+            checksum: u32::from_le_bytes([bytes[32], bytes[33], bytes[34], bytes[35]]),
+        };
+
+        if header.version != FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion(header.version))
+        }
+
+        if header.hash_algorithm != HASH_SIP24 && header.hash_algorithm != HASH_CUSTOM {
+            return Err(Error::UnsupportedHasher(header.hash_algorithm))
+        }
+
+        Ok(header)
+    }
+
+    /// Write the header at the cursor's position (which should be the start of the file).
+    pub fn write_to(&self, file: &mut File) -> Result<(), Error> {
+        file.write_all(&self.to_bytes()).map_err(Error::Io)
+    }
+
+    /// Get the checksum of the file, from the checksum of its rows (see above).
+    pub fn file_checksum(&self, rows_checksum: u32) -> u32 {
+        crc32c_append(rows_checksum, &self.to_bytes()[..32])
+    }
+
+    fn to_bytes(&self) -> [u8; HEADER_LEN as usize] {
+        let mut bytes = [0; HEADER_LEN as usize];
+        bytes[..8].copy_from_slice(MAGIC);
+        bytes[8..10].copy_from_slice(&self.version.to_le_bytes());
+        bytes[10] = self.hash_algorithm;
+        bytes[11] = self.flags;
+        bytes[12..16].copy_from_slice(&self.row_width.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.entries.to_le_bytes());
+        bytes[24..32].copy_from_slice(&self.hasher_check.to_le_bytes());
+        bytes[32..36].copy_from_slice(&self.checksum.to_le_bytes());
+        bytes
+    }
+}
@@ -1,81 +1,193 @@
-use SEP;
+use {Error, SEP};
 
-use siphasher::sip::SipHasher;
-use std::error::Error;
 use std::fs::{File, OpenOptions};
-use std::hash::{Hash, Hasher};
-use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
-use std::iter;
-
-/// Computes the hash for the given object using the built-in `SipHasher`
-pub fn hash<T: Hash>(obj: &T) -> u64 {
-    let mut hasher = SipHasher::new();
-    obj.hash(&mut hasher);
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufWriter, ErrorKind, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Computes the hash for the given bytes using a hasher from the given `BuildHasher`.
+/// This doesn't go through `std::hash::Hash`, so it only depends on the bytes
+/// (and not on the type they came from, or the platform we're running on).
+pub fn hash<S: BuildHasher>(build_hasher: &S, bytes: &[u8]) -> u64 {
+    let mut hasher = build_hasher.build_hasher();
+    hasher.write(bytes);
     hasher.finish()
 }
 
-/// Writes a line to the given buffer
-/// (pads the line with null bytes to fit to the given length)
-pub fn write_buffer(buf_writer: &mut BufWriter<&mut File>,
-                    line: &str, pad_length: &mut usize) -> Result<u64, String> {
-    let padding = if line.len() < *pad_length {
-        iter::repeat(SEP).take(*pad_length - line.len()).collect::<String>()
-    } else {
-        if line.len() > *pad_length {
-            *pad_length = line.len();   // change pad length if we get a giant string
+/// Read a little-endian `u32` from the start of the given bytes.
+pub fn read_u32(bytes: &[u8]) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(&bytes[..4]);
+    u32::from_le_bytes(buf)
+}
+
+/// Read a little-endian `u64` from the start of the given bytes.
+pub fn read_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(buf)
+}
+
+/// Writes a line to the given buffer (and returns the number of bytes written)
+pub fn write_buffer<W: Write>(buf_writer: &mut BufWriter<W>, line: &str) -> Result<u64, Error> {
+    let line = format!("{}\n", line);
+    try!(buf_writer.write_all(line.as_bytes()));
+    try!(buf_writer.flush());
+    Ok(line.len() as u64)
+}
+
+/// Escapes the backslashes, newlines, carriage returns and null bytes in the given string
+/// (so that it can be safely written as a row, or as a part of it)
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            SEP => escaped.push_str("\\0"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Reverses the escaping done by `escape` (returns `None` if it finds an invalid sequence)
+pub fn unescape(s: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue
         }
 
-        String::new()
-    };
+        unescaped.push(match chars.next() {
+            Some('\\') => '\\',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('0') => SEP,
+            _ => return None,
+        });
+    }
 
-    let line = format!("{}{}\n", line, padding);
-    let n = try!(buf_writer.write(line.as_bytes())
-                           .map_err(|e| format!("Cannot write line to buffer! ({})",
-                                                e.description())));
-    try!(buf_writer.flush()
-                   .map_err(|e| format!("Cannot flush the buffer to file!({})",
-                                        e.description())));
-    Ok(n as u64)
+    Some(unescaped)
 }
 
 /// Opens a file in read/write mode (or creates if it doesn't exist)
-pub fn create_or_open_file(path: &str) -> Result<File, String> {
+pub fn create_or_open_file(path: &str) -> Result<File, Error> {
     OpenOptions::new()
                 .read(true)
                 .write(true)
                 .create(true)
                 .open(path)
-                .map_err(|e| format!("Cannot create/open file at {}! ({})",
-                                     path, e.description()))
+                .map_err(Error::Io)
+}
+
+/// Get the directory of the given path (which is where all the files of a `HashFile` live)
+pub fn dir_of(path: &str) -> &Path {
+    match Path::new(path).parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// Sync the directory of the given path, so that the files created (or renamed, or removed)
+/// in there survive a crash.
+#[cfg(unix)]
+pub fn sync_dir(path: &str) -> Result<(), Error> {
+    File::open(dir_of(path)).and_then(|dir| dir.sync_all()).map_err(Error::Io)
+}
+
+/// (Windows doesn't let us open the directories, and it doesn't need this anyway.)
+#[cfg(not(unix))]
+pub fn sync_dir(_path: &str) -> Result<(), Error> {
+    Ok(())
 }
 
 /// Move the cursor to a position from the start of the file
 /// (since we're dealing with absolute positions in our API)
-pub fn seek_from_start(file: &mut File, pos: u64) -> Result<(), String> {
+pub fn seek_from_start(file: &mut File, pos: u64) -> Result<(), Error> {
     file.seek(SeekFrom::Start(pos))
         .map(|_| ())
-        .map_err(|e| format!("Cannot seek through file! ({})", e.description()))
+        .map_err(Error::Io)
 }
 
 /// Get the file size
-pub fn get_size(file: &File) -> Result<u64, String> {
+pub fn get_size(file: &File) -> Result<u64, Error> {
     file.metadata()
         .map(|m| m.len())
-        .map_err(|e| format!("Cannot obtain file metadata ({})", e.description()))
-}
-
-/// Read one line from the cursor's current position and pop newline (if any) from the end
-pub fn read_one_line(file: &mut File) -> Result<String, String> {
-    let mut reader = BufReader::new(file);
-    let mut line = String::new();
-    reader.read_line(&mut line).map(|_| {
-        if line.ends_with('\n') {
-            line.pop();
-            if line.ends_with('\r') {
-                line.pop();
-            }
+        .map_err(Error::Io)
+}
+
+/// Read some bytes at the given offset (without moving the cursor, so that a bunch of threads
+/// can read the same file at once)
+#[cfg(unix)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    use std::os::unix::fs::FileExt;
+    file.read_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    use std::os::windows::fs::FileExt;
+    file.seek_read(buf, offset)
+}
+
+/// Read the line starting at the given offset and pop newline (if any) from the end
+/// (the path is only for the errors).
+pub fn read_line_at(file: &File, path: &str, offset: u64) -> Result<String, Error> {
+    let mut line = vec![];
+    let mut buf = [0; 256];
+    let mut pos = offset;
+    loop {
+        let n = match read_at(file, &mut buf, pos) {
+            Ok(0) => break,     // EOF
+            Ok(n) => n,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(Error::Io(e)),
+        };
+
+        if let Some(end) = buf[..n].iter().position(|&b| b == b'\n') {
+            line.extend_from_slice(&buf[..end]);
+            break
+        }
+
+        line.extend_from_slice(&buf[..n]);
+        pos += n as u64;
+    }
+
+    into_line(line, path, offset)
+}
+
+/// Turn the bytes of the line (without the newline) at the given offset of the file (in
+/// the path) into a string. A line that isn't UTF-8 has been damaged somehow.
+pub fn into_line(mut line: Vec<u8>, path: &str, offset: u64) -> Result<String, Error> {
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+
+    String::from_utf8(line).map_err(|_| Error::CorruptRow {
+        path: path.to_owned(),
+        offset: offset,
+    })
+}
+
+/// Read (at most) the given number of bytes starting at the given offset of the file
+/// (it's shorter only if we hit the end of the file).
+pub fn read_block_at(file: &File, offset: u64, length: usize) -> Result<Vec<u8>, Error> {
+    let mut block = vec![0; length];
+    let mut filled = 0;
+    while filled < length {
+        match read_at(file, &mut block[filled..], offset + filled as u64) {
+            Ok(0) => break,     // EOF
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(Error::Io(e)),
         }
+    }
 
-        line
-    }).map_err(|e| format!("Cannot read line from file! ({})", e.description()))
+    block.truncate(filled);
+    Ok(block)
 }
@@ -17,8 +17,10 @@
 //!
 //! Hence, at any given moment, the upper limit for the memory eaten by this thing
 //! is set by its [capacity][capacity]. This gives us good control over the space-time
-//! trade-off. But, the flushing will take O(2<sup>n</sup>) time, depending on the
-//! processor and I/O speed, as it does things on the fly with the help of iterators.
+//! trade-off. Each flush writes the stuff (in sorted order) to a small "run" next to
+//! the file, and the runs are merged with the help of iterators every now and then,
+//! so that each row gets rewritten O(log-n) times (depending on the processor and I/O
+//! speed, that's where most of the time goes).
 //!
 //! After the [final manual flush][finish], the file can be stored, moved around, and
 //! since it makes use of binary search, values can be obtained in O(log-n) time
@@ -39,11 +41,45 @@
 //! [hash-file]: struct.HashFile.html
 //! [wiki]: https://en.wikipedia.org/wiki/B-tree#B-tree_usage_in_databases
 
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+#[cfg(feature = "mmap")]
+extern crate memmap2;
+extern crate crc32c;
 extern crate siphasher;
 
 pub const SEP: char = '\0';
 
+mod bloom;
+mod cache;
+mod checksum;
+mod commit;
+mod data;
+mod encoding;
+mod error;
+mod header;
+mod hasher;
 mod helpers;
+mod lock;
 mod hash_file;
+mod hash_file_reader;
+mod reader;
+mod recovery;
+mod run;
+mod search;
+mod spill;
+mod wal;
 
+pub use cache::CacheStats;
+pub use checksum::Verification;
+#[cfg(feature = "serde")]
+pub use encoding::Json;
+pub use encoding::{Encoding, Text};
+pub use error::Error;
 pub use hash_file::HashFile;
+pub use hash_file_reader::HashFileReader;
+pub use hasher::DefaultStableHasher;
+pub use recovery::{Recovery, recover};
+pub use search::SearchMode;
@@ -0,0 +1,42 @@
+use Error;
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::ErrorKind;
+
+/// The suffix of the lock file, which lives alongside the other files (it's never removed,
+/// since the lock is on the file itself, and not on whether it's there)
+pub const LOCK_SUFFIX: &'static str = ".lock";
+
+/// Take an advisory lock (like `flock`) for the `HashFile` in the given path - an exclusive
+/// one for writing, or a shared one for reading. The lock goes away along with the file,
+/// and this fails with `Error::Locked` if someone else has got in the way.
+///
+/// Returns `None` if we can't lock the file at all (say, on a read-only filesystem, where
+/// no one could be writing to it anyway, or on a platform which doesn't do locks).
+pub fn lock(path: &str, exclusive: bool) -> Result<Option<File>, Error> {
+    let lock_path = format!("{}{}", path, LOCK_SUFFIX);
+    // (the lock file is always empty, and it might be locked by someone else right now)
+    let opened = OpenOptions::new().read(true).write(true).create(true).truncate(false)
+                                   .open(&lock_path);
+    let file = match (opened, exclusive) {
+        (Ok(file), _) => file,
+        (Err(e), true) => return Err(Error::Io(e)),
+        // readers don't need to write anything, so they'll make do with what's there
+        (Err(_), false) => match File::open(&lock_path) {
+            Ok(file) => file,
+            Err(_) => return Ok(None),
+        },
+    };
+
+    let locked = match exclusive {
+        true => file.try_lock(),
+        false => file.try_lock_shared(),
+    };
+
+    match locked {
+        Ok(_) => Ok(Some(file)),
+        Err(TryLockError::WouldBlock) => Err(Error::Locked(path.to_owned())),
+        Err(TryLockError::Error(ref e)) if e.kind() == ErrorKind::Unsupported => Ok(None),
+        Err(TryLockError::Error(e)) => Err(Error::Io(e)),
+    }
+}
@@ -0,0 +1,177 @@
+use Error;
+use cache::{BLOCK_SIZE, BlockCache};
+use helpers::{into_line, read_block_at, read_line_at};
+
+#[cfg(feature = "mmap")]
+use helpers::get_size;
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
+
+use std::borrow::Cow;
+use std::cmp;
+use std::fs::File;
+use std::sync::Arc;
+#[cfg(feature = "mmap")]
+use std::str;
+
+/// Reads the lines (or the rows, which have a fixed width) at the given offsets of a file -
+/// from the memory-mapped bytes (with the `mmap` feature), or by positional reads (which
+/// don't move the cursor). Either way, it only needs a shared reference, so that it can be
+/// used from a bunch of threads at once.
+pub struct LineReader {
+    file: File,
+    path: String,       // (only for the errors)
+    #[cfg(feature = "mmap")]
+    map: Option<Mmap>,
+    cache: Option<Cached>,
+}
+
+/// The cache (if any) for the reads that go to the file.
+struct Cached {
+    cache: Arc<BlockCache>,
+    file: u64,          // our number in the cache
+}
+
+impl LineReader {
+    pub fn new(file: File, path: &str) -> Result<LineReader, Error> {
+        let mut reader = LineReader {
+            file: file,
+            path: path.to_owned(),
+            #[cfg(feature = "mmap")]
+            map: None,
+            cache: None,
+        };
+
+        try!(reader.remap());
+        Ok(reader)
+    }
+
+    /// Map the file once again, so that we can see the stuff that's been appended to it
+    /// (until then, the new lines are read from the file).
+    #[cfg(feature = "mmap")]
+    pub fn remap(&mut self) -> Result<(), Error> {
+        // Mapping a file is "unsafe", because the bytes could change under our feet if some
+        // other process messes with the file. We never modify the stuff we've written (the
+        // "key" files are replaced by renaming, and the "data" file is only appended to),
+        // so that's left to the user.
+        self.map = match try!(get_size(&self.file)) > 0 {
+            true => Some(try!(unsafe { Mmap::map(&self.file) })),
+            false => None,      // empty files can't be mapped
+        };
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "mmap"))]
+    pub fn remap(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Cache the stuff we read from the file - either the lines themselves (for the "data"
+    /// file), or the blocks around the rows (for the "key" files, whose rows are next to
+    /// each other). Reads from the mapped bytes don't go through the cache.
+    pub fn set_cache(&mut self, cache: Option<Arc<BlockCache>>) {
+        self.cache = cache.map(|cache| Cached {
+            file: cache.next_file(),
+            cache: cache,
+        });
+    }
+
+    /// Read the line starting at the given offset (without the newline).
+    pub fn line_at<'a>(&'a self, offset: u64) -> Result<Cow<'a, str>, Error> {
+        #[cfg(feature = "mmap")]
+        {
+            if let Some(line) = self.map.as_ref().and_then(|m| mapped_line(m, &self.path, offset)) {
+                return line.map(Cow::Borrowed)
+            }
+        }
+
+        match self.cache {
+            Some(ref cached) => self.cached_line(cached, offset),
+            None => read_line_at(&self.file, &self.path, offset),
+        }.map(Cow::Owned)
+    }
+
+    /// Read the given number of bytes starting at the given offset (it's shorter only if
+    /// we hit the end of the file).
+    pub fn bytes_at<'a>(&'a self, offset: u64, length: usize) -> Result<Cow<'a, [u8]>, Error> {
+        #[cfg(feature = "mmap")]
+        {
+            if let Some(ref map) = self.map {
+                if offset + length as u64 <= map.len() as u64 {
+                    let start = offset as usize;
+                    return Ok(Cow::Borrowed(&map[start..start + length]))
+                }
+            }
+        }
+
+        match self.cache {
+            Some(ref cached) => self.cached_bytes(cached, offset, length),
+            None => read_block_at(&self.file, offset, length),
+        }.map(Cow::Owned)
+    }
+
+    fn cached_line(&self, cached: &Cached, offset: u64) -> Result<String, Error> {
+        if let Some(bytes) = cached.cache.get(cached.file, offset) {
+            return into_line(bytes.to_vec(), &self.path, offset)
+        }
+
+        let line = try!(read_line_at(&self.file, &self.path, offset));
+        cached.cache.insert(cached.file, offset, Arc::new(line.clone().into_bytes()));
+        Ok(line)
+    }
+
+    /// Put together the bytes from the blocks they're in (most of the time, that's just one).
+    fn cached_bytes(&self, cached: &Cached, offset: u64, length: usize)
+                    -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::with_capacity(length);
+        let mut pos = offset;
+        while bytes.len() < length {
+            let start = pos - pos % BLOCK_SIZE;
+            let block = match cached.cache.get(cached.file, start) {
+                Some(block) => block,
+                None => {
+                    let block = Arc::new(try!(read_block_at(&self.file, start,
+                                                            BLOCK_SIZE as usize)));
+                    cached.cache.insert(cached.file, start, block.clone());
+                    block
+                },
+            };
+
+            let from = (pos - start) as usize;
+            if from >= block.len() {
+                break       // EOF
+            }
+
+            let to = cmp::min(block.len(), from + length - bytes.len());
+            bytes.extend_from_slice(&block[from..to]);
+            pos = start + to as u64;
+        }
+
+        Ok(bytes)
+    }
+}
+
+/// Get the line starting at the given offset of the mapped bytes (if it's
+/// entirely in there).
+#[cfg(feature = "mmap")]
+fn mapped_line<'a>(bytes: &'a [u8], path: &str, offset: u64)
+                   -> Option<Result<&'a str, Error>> {
+    if offset >= bytes.len() as u64 {
+        return None
+    }
+
+    let bytes = &bytes[offset as usize..];
+    bytes.iter().position(|&b| b == b'\n').map(|end| {
+        let line = match bytes[..end].last() {
+            Some(&b'\r') => &bytes[..end - 1],
+            _ => &bytes[..end],
+        };
+
+        // same as what we'd get from `read_line_at`
+        str::from_utf8(line).map_err(|_| Error::CorruptRow {
+            path: path.to_owned(),
+            offset: offset,
+        })
+    })
+}
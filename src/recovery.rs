@@ -0,0 +1,180 @@
+use Error;
+use commit::{MANIFEST_SUFFIX, finish_commit};
+use hash_file::{BLOOM_SUFFIX, DAT_SUFFIX, RUN_SUFFIX, SPILL_SUFFIX};
+use header::Header;
+use helpers::{dir_of, get_size, seek_from_start, sync_dir};
+use lock::lock;
+use run::TEMP_SUFFIX;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+
+/// What was cleaned up after a crash (see [`recover`][recover]).
+///
+/// [recover]: fn.recover.html
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Recovery {
+    /// Whether a commit (which was cut short by the crash) has been finished.
+    pub finished_commit: bool,
+    /// The paths of the leftover files (the temp files of the writes which didn't go
+    /// through, and the chunks spilled by `build_from_iter`) which have been removed.
+    pub removed_files: Vec<String>,
+    /// The number of bytes cut off from the end of the "data" file (the torn record of
+    /// a flush which didn't go through).
+    pub truncated_bytes: u64,
+    /// The number of entries put back into memory from the write-ahead log (this is only
+    /// set by `HashFile::new`, since `recover` doesn't touch the log).
+    pub log_entries: usize,
+}
+
+impl Recovery {
+    /// Whether there was nothing to recover (i.e., the files were closed properly).
+    pub fn is_clean(&self) -> bool {
+        !self.finished_commit && self.removed_files.is_empty() && self.truncated_bytes == 0 &&
+        self.log_entries == 0
+    }
+}
+
+/// Check whether the file name (minus the name of the main "key" file) is one of
+/// our leftovers.
+fn is_leftover(rest: &str) -> bool {
+    let is_numbered = |rest: &str, prefix: &str| {
+        rest.strip_prefix(prefix).is_some_and(|n| n.parse::<u64>().is_ok())
+    };
+
+    if is_numbered(rest, SPILL_SUFFIX) {
+        return true
+    }
+
+    let stem = match rest.strip_suffix(TEMP_SUFFIX) {
+        Some(stem) => stem,
+        None => return false,
+    };
+
+    stem.is_empty() || stem == DAT_SUFFIX || stem == BLOOM_SUFFIX || stem == MANIFEST_SUFFIX ||
+    is_numbered(stem, RUN_SUFFIX)
+}
+
+/// Make sure that the main "key" file (if there's one) is actually ours, before we go
+/// around touching the files next to it.
+fn check_header(path: &str) -> Result<(), Error> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(Error::Io(e)),
+    };
+
+    if try!(get_size(&file)) > 0 {
+        try!(Header::read_from(&mut file, path));
+    }
+
+    Ok(())
+}
+
+/// Cut off the torn record at the end of the "data" file (if we went down while a flush was
+/// appending to it), so that the new records don't go after the garbage. This returns the
+/// number of bytes that have been cut off.
+fn truncate_data(path: &str) -> Result<u64, Error> {
+    let data_path = format!("{}{}", path, DAT_SUFFIX);
+    let mut file = match OpenOptions::new().read(true).write(true).open(&data_path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(Error::Io(e)),
+    };
+
+    // every record ends with a newline (and there aren't any others, since they're escaped)
+    let size = try!(get_size(&file));
+    let mut end = size;
+    let mut buf = [0; 4096];
+    while end > 0 {
+        let start = end.saturating_sub(buf.len() as u64);
+        let chunk = &mut buf[..(end - start) as usize];
+        try!(seek_from_start(&mut file, start));
+        try!(file.read_exact(chunk));
+        match chunk.iter().rposition(|&b| b == b'\n') {
+            Some(i) => {
+                end = start + i as u64 + 1;
+                break
+            },
+            None => end = start,
+        }
+    }
+
+    if end < size {
+        try!(file.set_len(end));
+        try!(file.sync_all());
+    }
+
+    Ok(size - end)
+}
+
+/// Clean up after a crash of the `HashFile` in the given path. If it went down in the
+/// middle of a commit (say, while merging the runs), then the commit is finished (so that
+/// we end up with the new files). Then, the leftover temp files are removed, since they
+/// belong to the writes which didn't go through (and the files they were supposed to
+/// replace are still there), and so is the torn record at the end of the "data" file (if
+/// a flush was cut short). Nothing's touched if the main file isn't one of ours (it
+/// fails with `Error::InvalidHeader`, or `Error::UnsupportedVersion`).
+///
+/// ``` rust,no_run
+/// # fn main() -> Result<(), catalog::Error> {
+/// let recovery = try!(catalog::recover("/tmp/SAMPLE"));
+/// if !recovery.is_clean() {
+///     println!("recovered from a crash: {:?}", recovery);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+///
+/// `HashFile::new` does this anyway (and the result is in
+/// [`HashFile::recovery`][recovery]), so this is for cleaning up without opening the file.
+/// Like `HashFile::new`, this locks the file while it's at it (and so, it fails with
+/// `Error::Locked` if someone's using the file).
+///
+/// [recovery]: struct.HashFile.html#method.recovery
+pub fn recover(path: &str) -> Result<Recovery, Error> {
+    let _lock = try!(lock(path, true));
+    recover_locked(path)
+}
+
+/// Same as `recover`, but for when we've already got the lock.
+pub fn recover_locked(path: &str) -> Result<Recovery, Error> {
+    try!(check_header(path));
+    let mut recovery = Recovery {
+        finished_commit: try!(finish_commit(path)),
+        ..Recovery::default()
+    };
+
+    recovery.truncated_bytes = try!(truncate_data(path));
+
+    let name = match Path::new(path).file_name().and_then(|n| n.to_str()) {
+        Some(name) => name,
+        None => return Ok(recovery),
+    };
+
+    let dir = dir_of(path);
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(recovery),
+        Err(e) => return Err(Error::Io(e)),
+    };
+
+    for entry in entries {
+        let file_name = try!(entry).file_name();
+        let is_ours = file_name.to_str().and_then(|n| n.strip_prefix(name))
+                                        .is_some_and(is_leftover);
+        if is_ours {
+            let leftover = dir.join(&file_name);
+            try!(fs::remove_file(&leftover));
+            recovery.removed_files.push(leftover.to_string_lossy().into_owned());
+        }
+    }
+
+    if !recovery.removed_files.is_empty() {
+        recovery.removed_files.sort();
+        try!(sync_dir(path));
+    }
+
+    Ok(recovery)
+}
@@ -0,0 +1,620 @@
+use bloom::BloomFilter;
+use cache::BlockCache;
+use checksum::{ChecksumWriter, Verification};
+use data::DataReader;
+use header::{HEADER_LEN, HASHER_PROBE, Header};
+use helpers::{create_or_open_file, get_size, hash, read_u32, read_u64, seek_from_start};
+
+use reader::LineReader;
+use search::SearchMode;
+
+use Error;
+
+use crc32c::{crc32c, crc32c_append};
+
+use std::cmp::{self, Ordering};
+use std::fs::File;
+use std::hash::BuildHasher;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::iter::Peekable;
+use std::ops::AddAssign;
+use std::sync::Arc;
+
+pub const TEMP_SUFFIX: &'static str = ".hash_file";
+
+/// The rows (along with the hashes of their keys) from one of the sources of a merge.
+pub type Rows<'a> = Box<dyn Iterator<Item=Result<(u64, KeyIndex), Error>> + 'a>;
+
+/// The width of a row in the "key" files. The integers are stored in little-endian (like
+/// the header), and a row goes like so,
+///
+/// | bytes    | field                                                  |
+/// |----------|--------------------------------------------------------|
+/// | `0..8`   | hash of the key                                        |
+/// | `8..16`  | offset of the key (and the value) in the "data" file    |
+/// | `16..20` | overwritten count (the top two bits have the state)    |
+/// | `20..24` | checksum (CRC32C) of the stuff before it               |
+pub const ROW_LEN: u64 = 24;
+
+/// The state (`0` for alive, `1` for removed and `2` for revived) goes in the top two bits
+/// of the count (so, the count stops at about a billion).
+const STATE_SHIFT: u32 = 30;
+const MAX_COUNT: usize = (1 << STATE_SHIFT) - 1;
+
+#[derive(Clone)]
+pub struct KeyIndex {
+    pub key: Option<String>,    // encoded (and escaped) key, as it appears in the "data" file
+                                // (the rows don't have it, so it's read only when we need it)
+    pub value: Option<String>,  // (escaped) value, if it's been read along with the key (so
+                                // that we don't have to read the same record once again)
+    pub idx: u64,               // offset of the record (the key and the value) in that file
+    pub count: usize,
+    pub removed: bool,          // tombstone (the row and its value are dropped while merging
+                                // into the main file)
+    pub revived: bool,          // written after a tombstone (so, the older rows don't count)
+}
+
+impl KeyIndex {
+    pub fn new(key: String) -> KeyIndex {
+        KeyIndex {
+            key: Some(key),
+            value: None,
+            idx: 0,
+            count: 0,
+            removed: false,
+            revived: false,
+        }
+    }
+
+    pub fn tombstone(key: String) -> KeyIndex {
+        KeyIndex {
+            removed: true,
+            ..KeyIndex::new(key)
+        }
+    }
+
+    /// Parse a row (starting at the given offset of the file in the path), along with the
+    /// hash of its key.
+    pub fn from_row(row: &[u8], path: &str, offset: u64) -> Result<(u64, KeyIndex), Error> {
+        let corrupt = || Error::CorruptRow {
+            path: path.to_owned(),
+            offset: offset,
+        };
+
+        if row.len() as u64 != ROW_LEN {
+            return Err(corrupt())
+        }
+
+        if crc32c(&row[..20]) != read_u32(&row[20..]) {
+            return Err(Error::ChecksumMismatch {
+                path: path.to_owned(),
+                offset: offset,
+            })
+        }
+
+        let count = read_u32(&row[16..]);
+        let state = count >> STATE_SHIFT;
+        if state > 2 {
+            return Err(corrupt())
+        }
+
+        Ok((read_u64(&row[..8]), KeyIndex {
+            key: None,
+            value: None,
+            idx: read_u64(&row[8..]),
+            count: (count as usize) & MAX_COUNT,
+            removed: state == 1,
+            revived: state == 2,
+        }))
+    }
+
+    /// Put together the row for this key (with the given hash).
+    pub fn to_row(&self, hash: u64) -> [u8; ROW_LEN as usize] {
+        let state = match (self.removed, self.revived) {
+            (true, _) => 1,
+            (false, true) => 2,
+            (false, false) => 0,
+        };
+
+        let count = cmp::min(self.count, MAX_COUNT) as u32 | state << STATE_SHIFT;
+        let mut row = [0; ROW_LEN as usize];
+        row[..8].copy_from_slice(&hash.to_le_bytes());
+        row[8..16].copy_from_slice(&self.idx.to_le_bytes());
+        row[16..20].copy_from_slice(&count.to_le_bytes());
+        let checksum = crc32c(&row[..20]);
+        row[20..].copy_from_slice(&checksum.to_le_bytes());
+        row
+    }
+
+    /// Get the key (reading it from the "data" file, if we don't have it already). The value
+    /// is in the same record, so it's kept around for `take_value`.
+    pub fn load_key(&mut self, data: &DataReader) -> Result<&str, Error> {
+        if self.key.is_none() {
+            let (key, value) = try!(data.record_at(self.idx));
+            self.key = Some(key);
+            self.value = value;
+        }
+
+        Ok(self.key.as_ref().map_or("", |k| k.as_str()))
+    }
+
+    /// Get the value (if we've read it along with the key, or from the "data" file).
+    pub fn take_value(&mut self, data: &DataReader) -> Result<String, Error> {
+        match self.value.take() {
+            Some(value) => Ok(value),
+            None => data.entry_at(self.idx).map(|(_, value)| value),
+        }
+    }
+}
+
+impl AddAssign for KeyIndex {
+    fn add_assign(&mut self, other: KeyIndex) {
+        if self.key.is_none() {
+            self.key = other.key;
+        }
+
+        if other.removed {
+            self.removed = true;
+            self.revived = false;
+            return
+        }
+
+        // a key that's revived after removal starts afresh, and it should hide whatever's
+        // older than this (the other one also carries the number of times it's been
+        // overwritten while it was in memory)
+        if self.removed || other.revived {
+            self.count = other.count;
+            self.revived = true;
+        } else {
+            self.count += 1 + other.count;
+        }
+
+        self.idx = other.idx;
+        self.value = other.value;
+        self.removed = false;
+    }
+}
+
+/// A sorted "key" file - either the main file, or one of the runs written on top of it by
+/// the flushes. Each run has its own header, followed by the rows (see `ROW_LEN`).
+pub struct Run {
+    pub path: String,
+    reader: LineReader,
+    pub rows: u64,
+    pub bloom: Option<BloomFilter>,     // only for the main file (if there's a ".bloom" file)
+    fences: Option<Fences>,
+}
+
+impl Run {
+    /// Open the run in the given path and check its header. The file is created if it
+    /// doesn't exist (in which case, it's an empty run without a header).
+    pub fn open<S: BuildHasher>(path: &str, hasher: &S) -> Result<Run, Error> {
+        let file = try!(create_or_open_file(path));
+        Run::from_file(path, file, hasher)
+    }
+
+    /// Open an existing run (without creating it, or writing to it).
+    pub fn open_read_only<S: BuildHasher>(path: &str, hasher: &S) -> Result<Run, Error> {
+        let file = try!(File::open(path));
+        Run::from_file(path, file, hasher)
+    }
+
+    fn from_file<S: BuildHasher>(path: &str, mut file: File, hasher: &S) -> Result<Run, Error> {
+        let rows = match try!(get_size(&file)) > 0 {
+            true => {
+                let header = try!(Header::read_from(&mut file, path));
+                if header.row_width as u64 != ROW_LEN {
+                    return Err(Error::InvalidHeader(path.to_owned()))
+                }
+
+                if header.hasher_check != hash(hasher, HASHER_PROBE) {
+                    return Err(Error::HasherMismatch)
+                }
+
+                header.entries
+            },
+            false => 0,
+        };
+
+        Ok(Run {
+            path: path.to_owned(),
+            reader: try!(LineReader::new(file, path)),
+            rows: rows,
+            bloom: None,
+            fences: None,
+        })
+    }
+
+    /// Iterate over the rows (along with the hashes of their keys) using a separate file
+    /// descriptor, so that a bunch of runs can be read side by side. Rows that we can't
+    /// read (or whose checksums don't match) come out as errors, so that a merge doesn't
+    /// quietly leave them behind.
+    pub fn entries<'a>(&'a self) -> Result<Rows<'a>, Error> {
+        let mut file = try!(File::open(&self.path));
+        try!(seek_from_start(&mut file, HEADER_LEN));
+        let mut reader = BufReader::new(file);
+        Ok(Box::new((0..self.rows).map(move |pos| {
+            let offset = HEADER_LEN + pos * ROW_LEN;
+            let mut row = [0; ROW_LEN as usize];
+            match reader.read_exact(&mut row) {
+                Ok(_) => KeyIndex::from_row(&row, &self.path, offset),
+                // the header says that there are more rows than what we've got
+                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => Err(Error::CorruptRow {
+                    path: self.path.clone(),
+                    offset: offset,
+                }),
+                Err(e) => Err(Error::Io(e)),
+            }
+        })))
+    }
+
+    /// Find the row for the given key (and its hash) in this run. The keys are in the "data"
+    /// file, and they're only read from there when we find a row with the same hash.
+    pub fn find(&self, data: &DataReader, mode: SearchMode, hashed_key: u64, key: &str)
+                -> Result<Option<KeyIndex>, Error> {
+        if let Some(ref bloom) = self.bloom {
+            if !bloom.contains(hashed_key) {
+                return Ok(None)     // definitely not in here
+            }
+        }
+
+        // we search through the rows (not bytes), so that we never land beyond the last row
+        let mut range = match self.fences {
+            Some(ref fences) => fences.range(hashed_key, self.rows),
+            None => Range {
+                low: 0,
+                high: self.rows,
+                low_hash: 0,
+                high_hash: u64::MAX,
+            },
+        };
+
+        if mode == SearchMode::Interpolation {
+            // not worth it for a handful of rows
+            while range.high - range.low > 8 {
+                let length = range.high - range.low;
+                let pos = range.estimate(hashed_key);
+                if let Some(key_idx) = try!(self.probe(data, pos, hashed_key, key, &mut range)) {
+                    return Ok(Some(key_idx))
+                }
+
+                // The estimate is usually off by about `sqrt(n)` rows, so we read the row
+                // at that distance (on the side of the key) to trap the key in between.
+                let gap = (length as f64).sqrt() as u64 + 1;
+                if range.low < range.high {
+                    let guard = match range.low > pos {
+                        true => cmp::min(pos + gap, range.high - 1),
+                        false => cmp::max(pos.saturating_sub(gap), range.low),
+                    };
+
+                    if let Some(key_idx) = try!(self.probe(data, guard, hashed_key,
+                                                           key, &mut range)) {
+                        return Ok(Some(key_idx))
+                    }
+                }
+
+                if range.high - range.low > length / 2 {
+                    break       // the hashes aren't uniform in here (so, bisect the rest)
+                }
+            }
+        }
+
+        while range.low < range.high {
+            let mid = (range.low + range.high) / 2;
+            if let Some(key_idx) = try!(self.probe(data, mid, hashed_key, key, &mut range)) {
+                return Ok(Some(key_idx))
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Cache the blocks we read from the file (see `LineReader::set_cache`).
+    pub fn set_cache(&mut self, cache: Option<Arc<BlockCache>>) {
+        self.reader.set_cache(cache);
+    }
+
+    /// Load the hashes of every `every`-th row into memory (see `Fences`), or drop them
+    /// if that's zero.
+    pub fn load_fences(&mut self, every: u64) -> Result<(), Error> {
+        if every == 0 {
+            self.fences = None;
+            return Ok(())
+        }
+
+        let mut hashes = Vec::with_capacity((self.rows / every + 1) as usize);
+        let mut pos = 0;
+        while pos < self.rows {
+            let offset = HEADER_LEN + pos * ROW_LEN;
+            let row = try!(self.reader.bytes_at(offset, ROW_LEN as usize));
+            hashes.push(try!(KeyIndex::from_row(&row, &self.path, offset)).0);
+            pos += every;
+        }
+
+        self.fences = Some(Fences {
+            every: every,
+            hashes: hashes,
+        });
+
+        Ok(())
+    }
+
+    /// Compare the row at the given position with the key, and narrow down the range
+    /// (or return the row, if it's the one we're looking for).
+    fn probe(&self, data: &DataReader, pos: u64, hashed_key: u64, key: &str,
+             range: &mut Range) -> Result<Option<KeyIndex>, Error> {
+        let offset = HEADER_LEN + pos * ROW_LEN;
+        let row = try!(self.reader.bytes_at(offset, ROW_LEN as usize));
+        // a damaged row could send us the wrong way (so, we check each one we land on)
+        let (row_hash, mut key_idx) = try!(KeyIndex::from_row(&row, &self.path, offset));
+
+        // we only need the key itself in case of collisions
+        let ordering = match row_hash.cmp(&hashed_key) {
+            Ordering::Equal => try!(key_idx.load_key(data)).cmp(key),
+            ordering => ordering,
+        };
+
+        match ordering {
+            Ordering::Equal => return Ok(Some(key_idx)),
+            Ordering::Less => {
+                range.low = pos + 1;
+                range.low_hash = row_hash;
+            },
+            Ordering::Greater => {
+                range.high = pos;
+                range.high_hash = row_hash;
+            },
+        }
+
+        Ok(None)
+    }
+
+    /// Check the rows (and the whole file) against their checksums, and note down the ones
+    /// that don't match in the report.
+    pub fn verify(&self, report: &mut Verification) -> Result<(), Error> {
+        let mut file = try!(File::open(&self.path));
+        if try!(get_size(&file)) == 0 {
+            return Ok(())
+        }
+
+        let header = try!(Header::read_from(&mut file, &self.path));
+        let mut reader = BufReader::new(file);
+        let mut checksum = 0;
+        let mut offset = HEADER_LEN;
+        let mut row = Vec::with_capacity(ROW_LEN as usize);
+        loop {
+            row.clear();
+            let n = try!((&mut reader).take(ROW_LEN).read_to_end(&mut row));
+            if n == 0 {
+                break
+            }
+
+            checksum = crc32c_append(checksum, &row);
+            if KeyIndex::from_row(&row, &self.path, offset).is_err() {
+                report.bad_rows.push((self.path.clone(), offset));
+            }
+
+            offset += n as u64;
+        }
+
+        if header.file_checksum(checksum) != header.checksum {
+            report.bad_files.push(self.path.clone());
+        }
+
+        Ok(())
+    }
+}
+
+/// The hashes of every `every`-th row of a run (since the rows have the same width, we
+/// don't need their offsets), which narrow down the search to a block of rows before
+/// we touch the file.
+struct Fences {
+    every: u64,
+    hashes: Vec<u64>,
+}
+
+impl Fences {
+    /// Get the block of rows that could have the key.
+    fn range(&self, hashed_key: u64, rows: u64) -> Range {
+        // the key comes after the fences with smaller hashes, and before the ones with
+        // bigger hashes (the fences with the same hash could be either way)
+        let before = self.hashes.partition_point(|&h| h < hashed_key);
+        let after = self.hashes.partition_point(|&h| h <= hashed_key);
+        Range {
+            low: before.saturating_sub(1) as u64 * self.every,
+            high: cmp::min(after as u64 * self.every, rows),
+            low_hash: if before > 0 { self.hashes[before - 1] } else { 0 },
+            high_hash: self.hashes.get(after).cloned().unwrap_or(u64::MAX),
+        }
+    }
+}
+
+/// The rows that could have the key we're looking for, along with the hashes at either end
+/// (the rows in between can only have the hashes in this range).
+struct Range {
+    low: u64,
+    high: u64,
+    low_hash: u64,
+    high_hash: u64,
+}
+
+impl Range {
+    /// Estimate the position of the key from where its hash stands between the hashes
+    /// at either end (assuming that they're spread evenly).
+    fn estimate(&self, hashed_key: u64) -> u64 {
+        let length = self.high - self.low;
+        let share = hashed_key.saturating_sub(self.low_hash) as u128 * length as u128 /
+                    (self.high_hash.saturating_sub(self.low_hash) as u128 + 1);
+        self.low + cmp::min(share as u64, length - 1)
+    }
+}
+
+/// Merges the rows from a bunch of sorted sources (from the oldest to the newest), and throws
+/// the rows for each key (put together, along with the hash of the key) in ascending order.
+/// If one of the sources fails, then its error is thrown (and the merge should stop there).
+pub struct Merged<'a> {
+    inputs: Vec<Peekable<Rows<'a>>>,
+    data: &'a DataReader,       // for the keys (in case a bunch of rows have the same hash)
+}
+
+impl<'a> Merged<'a> {
+    pub fn new(inputs: Vec<Rows<'a>>, data: &'a DataReader) -> Merged<'a> {
+        Merged {
+            inputs: inputs.into_iter().map(|i| i.peekable()).collect(),
+            data: data,
+        }
+    }
+}
+
+impl<'a> Iterator for Merged<'a> {
+    type Item = Result<(u64, KeyIndex), Error>;
+
+    fn next(&mut self) -> Option<Result<(u64, KeyIndex), Error>> {
+        // all the inputs throw the rows in ascending order
+        let mut hash = None;
+        for input in self.inputs.iter_mut() {
+            match input.peek() {
+                Some(&Err(_)) => return input.next(),
+                Some(&Ok((h, _))) if hash.is_none_or(|min| h < min) => hash = Some(h),
+                _ => (),
+            }
+        }
+
+        let hash = hash?;
+
+        let mut next = vec![];
+        for input in self.inputs.iter_mut() {
+            if let Some(&Ok((h, _))) = input.peek() {
+                if h == hash {
+                    next.push(input);
+                }
+            }
+        }
+
+        // We need the keys only if there's more than one row with this hash (which is
+        // usually the same key, but it could be a collision).
+        let mut key = None;
+        if next.len() > 1 {
+            for input in next.iter_mut() {
+                if let Some(&mut Ok((_, ref mut key_idx))) = input.peek_mut() {
+                    let row_key = match key_idx.load_key(self.data) {
+                        Ok(row_key) => row_key,
+                        Err(e) => return Some(Err(e)),
+                    };
+
+                    if key.as_ref().is_none_or(|k: &String| row_key < k.as_str()) {
+                        key = Some(row_key.to_owned());
+                    }
+                }
+            }
+        }
+
+        // put together the rows for this key, from the oldest to the newest
+        let mut merged: Option<KeyIndex> = None;
+        for input in next {
+            let is_next = key.is_none() || match input.peek() {
+                Some(&Ok((_, ref k))) => k.key == key,
+                _ => false,
+            };
+
+            if !is_next {
+                continue
+            }
+
+            if let Some(Ok((_, key_idx))) = input.next() {
+                merged = Some(match merged {
+                    Some(mut m) => { m += key_idx; m },
+                    None => key_idx,
+                });
+            }
+        }
+
+        merged.map(|key_idx| Ok((hash, key_idx)))
+    }
+}
+
+/// Look for the key in the runs (which should go from the newest to the oldest), and put
+/// together its row, along with the given one (if any), which is newer than all of these.
+/// Returns `None` if the key isn't there (or if it's been removed).
+pub fn lookup<'a, I>(runs: I, data: &DataReader, mode: SearchMode, hashed_key: u64, key: &str,
+                     newest: Option<KeyIndex>) -> Result<Option<KeyIndex>, Error>
+    where I: Iterator<Item=&'a Run>
+{
+    // a revived key starts afresh, so we don't need anything older
+    let is_oldest = |k: &KeyIndex| k.removed || k.revived;
+    let mut found = vec![];
+    if let Some(key_idx) = newest {
+        found.push(key_idx);
+    }
+
+    if !found.first().is_some_and(&is_oldest) {
+        for run in runs {
+            if let Some(key_idx) = try!(run.find(data, mode, hashed_key, key)) {
+                let done = is_oldest(&key_idx);
+                found.push(key_idx);
+                if done {
+                    break
+                }
+            }
+        }
+    }
+
+    let mut rows = found.into_iter().rev();
+    let mut key_idx = match rows.next() {
+        Some(key_idx) => key_idx,
+        None => return Ok(None),
+    };
+
+    for row in rows {
+        key_idx += row;
+    }
+
+    Ok(if key_idx.removed { None } else { Some(key_idx) })
+}
+
+/// Writes (already sorted) rows into a temporary file, which replaces the run in the given
+/// path once it's committed (see `Commit`).
+pub struct RunWriter {
+    temp_path: String,
+    writer: BufWriter<ChecksumWriter<File>>,
+    rows: u64,
+}
+
+impl RunWriter {
+    pub fn new(path: &str) -> Result<RunWriter, Error> {
+        let temp_path = format!("{}{}", path, TEMP_SUFFIX);
+        let mut file = try!(File::create(&temp_path));
+        // the header goes in once we know how many rows we've got
+        try!(seek_from_start(&mut file, HEADER_LEN));
+
+        Ok(RunWriter {
+            temp_path: temp_path,
+            writer: BufWriter::new(ChecksumWriter::new(file)),
+            rows: 0,
+        })
+    }
+
+    pub fn push(&mut self, hash: u64, key_index: &KeyIndex) -> Result<(), Error> {
+        try!(self.writer.write_all(&key_index.to_row(hash)));
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// The number of rows written so far.
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    /// Write the header (with the hasher's fingerprint and the checksum), and sync the file
+    /// to the disk. Returns the path of the temp file, which should be renamed over the run.
+    pub fn finish<S: BuildHasher>(self, hasher: &S, flags: u8) -> Result<String, Error> {
+        let writer = try!(self.writer.into_inner().map_err(|e| Error::Io(e.into_error())));
+        let (mut file, checksum) = writer.into_inner();
+        let mut header = Header::for_hasher(hasher, ROW_LEN as u32, self.rows, flags);
+        header.checksum = header.file_checksum(checksum);
+        try!(seek_from_start(&mut file, 0));
+        try!(header.write_to(&mut file));
+        try!(file.sync_all());
+        Ok(self.temp_path)
+    }
+}
@@ -0,0 +1,19 @@
+/// The way [`HashFile`][hash-file] searches for a key through the sorted rows of its files.
+///
+/// [hash-file]: struct.HashFile.html
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SearchMode {
+    /// Plain old binary search, which takes about `log2(n)` reads for `n` rows (no matter
+    /// what the hashes look like).
+    #[default]
+    Binary,
+    /// Since the rows are sorted by the hashes of the keys (which are pretty much uniform
+    /// over the `u64` range), the position of a key can be estimated from its hash.
+    /// Each estimate is followed by a "guard" read (at around `sqrt(n)` rows away from it),
+    /// which traps the key in a much smaller range, and the estimates go on from there.
+    /// So, most of the reads are close to each other, and only one or two of them land
+    /// on some far-off place in the file. If an estimate doesn't even halve the range
+    /// (which happens when the hashes aren't uniform, say, with a bad hasher), then we
+    /// fall back to binary search.
+    Interpolation,
+}
@@ -0,0 +1,88 @@
+use {Error, SEP};
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Lines, Write};
+
+/// A key/value pair in a sorted chunk, which is spilled to a temp file while building
+/// a `HashFile` from an iterator.
+pub struct SpillEntry {
+    pub hash: u64,
+    pub key: String,        // encoded (and escaped) key
+    pub count: usize,       // number of times the key's been overwritten in this chunk
+    pub value: String,      // encoded (and escaped) value
+}
+
+impl SpillEntry {
+    /// Parse a line (starting at the given offset of the file in the path).
+    fn from_line(line: &str, path: &str, offset: u64) -> Result<SpillEntry, Error> {
+        let corrupt = || Error::CorruptRow {
+            path: path.to_owned(),
+            offset: offset,
+        };
+
+        let mut split = line.split(SEP);
+        let (hash, key, count, value) = (split.next(), split.next(), split.next(), split.next());
+        Ok(SpillEntry {
+            hash: try!(hash.unwrap_or("").parse::<u64>().map_err(|_| corrupt())),
+            key: try!(key.ok_or_else(corrupt)).to_owned(),
+            count: try!(count.unwrap_or("").parse::<usize>().map_err(|_| corrupt())),
+            value: try!(value.ok_or_else(corrupt)).to_owned(),
+        })
+    }
+}
+
+impl Display for SpillEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}{}{}{}{}{}", self.hash, SEP, self.key, SEP, self.count, SEP, self.value)
+    }
+}
+
+/// Write the chunk (which is already sorted, since it's a `BTreeMap`) to the given path.
+pub fn write_spill(path: &str, chunk: BTreeMap<(u64, String), (usize, String)>)
+                   -> Result<(), Error> {
+    let mut writer = BufWriter::new(try!(File::create(path)));
+    for ((hash, key), (count, value)) in chunk {
+        let entry = SpillEntry {
+            hash: hash,
+            key: key,
+            count: count,
+            value: value,
+        };
+
+        try!(writeln!(writer, "{}", entry));
+    }
+
+    writer.flush().map_err(Error::Io)
+}
+
+/// Reads the entries of a spilled chunk (in the order they were written).
+pub struct SpillReader {
+    path: String,
+    lines: Lines<BufReader<File>>,
+    offset: u64,
+}
+
+impl SpillReader {
+    pub fn open(path: &str) -> Result<SpillReader, Error> {
+        Ok(SpillReader {
+            path: path.to_owned(),
+            lines: BufReader::new(try!(File::open(path))).lines(),
+            offset: 0,
+        })
+    }
+}
+
+impl Iterator for SpillReader {
+    type Item = Result<SpillEntry, Error>;
+
+    fn next(&mut self) -> Option<Result<SpillEntry, Error>> {
+        self.lines.next().map(|line| {
+            let line = try!(line);
+            let offset = self.offset;
+            self.offset += line.len() as u64 + 1;
+            SpillEntry::from_line(&line, &self.path, offset)
+        })
+    }
+}
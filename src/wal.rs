@@ -0,0 +1,88 @@
+use {Error, SEP};
+use helpers::sync_dir;
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::str;
+
+/// The suffix of the write-ahead log, which lives alongside the other files
+pub const WAL_SUFFIX: &'static str = ".wal";
+
+/// An insertion (or a removal, if there's no value) in the write-ahead log. A line goes
+/// like `hash\0key\0value` (or `hash\0key` for a removal).
+pub struct LogEntry {
+    pub hash: u64,
+    pub key: String,                // encoded (and escaped) key
+    pub value: Option<String>,      // encoded (and escaped) value
+}
+
+impl LogEntry {
+    fn from_line(line: &str) -> Option<LogEntry> {
+        let mut split = line.splitn(3, SEP);
+        let (hash, key, value) = (split.next(), split.next(), split.next());
+        Some(LogEntry {
+            hash: hash?.parse().ok()?,
+            key: key?.to_owned(),
+            value: value.map(|v| v.to_owned()),
+        })
+    }
+}
+
+/// Open the log in the given path for appending (creating it if it doesn't exist).
+pub fn open_log(path: &str) -> Result<File, Error> {
+    let file = try!(OpenOptions::new().append(true).create(true).open(path));
+    // so that the log doesn't disappear along with the directory entry
+    try!(sync_dir(path));
+    Ok(file)
+}
+
+/// Append an entry to the log. It's written in one go (so that it's in the file even if
+/// we die right after this), but it's not synced to the disk.
+pub fn append_log(file: &mut File, hash: u64, key: &str, value: Option<&str>)
+                  -> Result<(), Error> {
+    let line = match value {
+        Some(value) => format!("{}{}{}{}{}\n", hash, SEP, key, SEP, value),
+        None => format!("{}{}{}\n", hash, SEP, key),
+    };
+
+    file.write_all(line.as_bytes()).map_err(Error::Io)
+}
+
+/// Read the entries in the log in the given path (if there's one). If we went down while
+/// writing an entry, then it's cut short, and so it's thrown out (along with whatever comes
+/// after it), and the log is truncated to the good part.
+pub fn read_log(path: &str) -> Result<Vec<LogEntry>, Error> {
+    let file = match OpenOptions::new().read(true).write(true).open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(Error::Io(e)),
+    };
+
+    let mut entries = vec![];
+    let mut good_length = 0;
+    {
+        let mut reader = BufReader::new(&file);
+        let mut line = vec![];
+        loop {
+            line.clear();
+            let n = try!(reader.read_until(b'\n', &mut line));
+            if n == 0 || line.last() != Some(&b'\n') {
+                break
+            }
+
+            match str::from_utf8(&line[..n - 1]).ok().and_then(LogEntry::from_line) {
+                Some(entry) => entries.push(entry),
+                None => break,
+            }
+
+            good_length += n as u64;
+        }
+    }
+
+    if good_length < try!(file.metadata()).len() {
+        try!(file.set_len(good_length));
+        try!(file.sync_all());
+    }
+
+    Ok(entries)
+}
@@ -0,0 +1,96 @@
+extern crate catalog;
+
+mod common;
+
+use catalog::{Error, HashFile};
+
+use common::{TempDir, file_names};
+
+use std::fs;
+
+/// (the size of the header of a "key" file, and that of each of its rows)
+const HEADER_LEN: usize = 40;
+const ROW_LEN: usize = 24;
+
+/// Create a (finished) `HashFile` in the path with a bunch of keys, and a bloom filter.
+fn create_with_bloom(path: &str) {
+    let mut hf: HashFile<usize, String> =
+        HashFile::new(path).unwrap().set_capacity(1000).set_bloom_filter(0.01);
+    for key in 0..1000 {
+        hf.insert(key, "foo".to_owned()).unwrap();
+    }
+
+    hf.finish().unwrap();
+}
+
+/// Damage the checksums of all the rows in the main file, so that we know when `get`
+/// goes looking through them (it fails if it does).
+fn damage_rows(path: &str) {
+    let mut bytes = fs::read(path).unwrap();
+    let mut offset = HEADER_LEN;
+    while offset < bytes.len() {
+        bytes[offset + 20] ^= 1;
+        offset += ROW_LEN;
+    }
+
+    fs::write(path, bytes).unwrap();
+}
+
+/// Get the number of missing keys that we've found out about without searching.
+fn skipped_misses(hf: &HashFile<usize, String>) -> usize {
+    (1000..1100).filter(|key| match hf.get(key) {
+        Ok(None) => true,
+        Err(Error::ChecksumMismatch { .. }) => false,
+        result => panic!("unexpected result: {:?}", result),
+    }).count()
+}
+
+#[test]
+fn test_bloom_filter_is_written_and_loaded() {
+    let dir = TempDir::new("bloom-loaded");
+    let path = dir.path();
+    create_with_bloom(&path);
+    assert_eq!(vec!["map", "map.bloom", "map.dat", "map.lock"], file_names(&path));
+
+    damage_rows(&path);
+    // (we don't have to ask for the filter to use it)
+    let hf: HashFile<usize, String> = HashFile::new(&path).unwrap();
+    // (there could be a false positive, or two)
+    assert!(skipped_misses(&hf) >= 95);
+    match hf.get(&7) {
+        Err(Error::ChecksumMismatch { .. }) => (),
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
+
+#[test]
+fn test_stale_bloom_filter_is_ignored() {
+    let dir = TempDir::new("bloom-stale");
+    let path = dir.path();
+    create_with_bloom(&path);
+
+    // the filter says it's for some other number of rows
+    let bloom_path = format!("{}.bloom", path);
+    let mut bytes = fs::read(&bloom_path).unwrap();
+    bytes[24..32].copy_from_slice(&1001u64.to_le_bytes());
+    fs::write(&bloom_path, bytes).unwrap();
+
+    damage_rows(&path);
+    let hf: HashFile<usize, String> = HashFile::new(&path).unwrap();
+    assert_eq!(0, skipped_misses(&hf));
+}
+
+#[test]
+fn test_bloom_filter_is_removed_without_asking() {
+    let dir = TempDir::new("bloom-removed");
+    let path = dir.path();
+    create_with_bloom(&path);
+
+    // the main file is rewritten, without a filter this time
+    let mut hf: HashFile<usize, String> = HashFile::new(&path).unwrap();
+    hf.insert(1000, "bar".to_owned()).unwrap();
+    hf.finish().unwrap();
+    assert_eq!(vec!["map", "map.dat", "map.lock"], file_names(&path));
+    assert_eq!(Some(("bar".to_owned(), 0)), hf.get(&1000).unwrap());
+    assert_eq!(None, hf.get(&1001).unwrap());
+}
@@ -0,0 +1,46 @@
+extern crate catalog;
+
+mod common;
+
+use catalog::HashFile;
+
+use common::TempDir;
+
+use std::fs;
+
+/// Some pairs with a bunch of repeated keys (and values with stuff that needs escaping).
+fn pairs() -> Vec<(usize, String)> {
+    (0..3000).map(|i| (i % 1700, format!("v{}\n\0{}", i, i % 7))).collect()
+}
+
+#[test]
+fn test_build_from_iter_matches_finish() {
+    let built_dir = TempDir::new("build-from-iter");
+    let built_path = built_dir.path();
+    // a small budget, so that the pairs are spilled in a bunch of chunks
+    let built: HashFile<usize, String> =
+        HashFile::build_from_iter(&built_path, pairs(), 16 * 1024).unwrap();
+    drop(built);
+
+    let inserted_dir = TempDir::new("build-insert");
+    let inserted_path = inserted_dir.path();
+    let mut inserted: HashFile<usize, String> =
+        HashFile::new(&inserted_path).unwrap().set_capacity(250);
+    for (key, value) in pairs() {
+        inserted.insert(key, value).unwrap();
+    }
+
+    inserted.finish().unwrap();
+    drop(inserted);
+
+    for suffix in &["", ".dat"] {
+        let built_bytes = fs::read(format!("{}{}", built_path, suffix)).unwrap();
+        let inserted_bytes = fs::read(format!("{}{}", inserted_path, suffix)).unwrap();
+        assert!(built_bytes == inserted_bytes, "the files ({:?}) don't match", suffix);
+    }
+
+    let hf: HashFile<usize, String> = HashFile::new(&built_path).unwrap();
+    assert_eq!(Some(("v1701\n\u{0}0".to_owned(), 1)), hf.get(&1).unwrap());
+    assert_eq!(Some(("v1699\n\u{0}5".to_owned(), 0)), hf.get(&1699).unwrap());
+    assert_eq!(1700, hf.len().unwrap());
+}
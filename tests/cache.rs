@@ -0,0 +1,70 @@
+// (the mapped bytes don't go through the cache)
+#![cfg(not(feature = "mmap"))]
+
+extern crate catalog;
+
+mod common;
+
+use catalog::HashFile;
+
+use common::{TempDir, create};
+
+#[test]
+fn test_hit_reads_the_record_once() {
+    let dir = TempDir::new("cache-once");
+    let path = dir.path();
+    create(&path, &[1], "foo");
+
+    // (everything we read goes through the cache, so the misses are the reads)
+    let hf: HashFile<usize, String> = HashFile::new(&path).unwrap().set_cache_size(1 << 20);
+    assert_eq!(Some(("foo".to_owned(), 0)), hf.get(&1).unwrap());
+    let stats = hf.cache_stats();
+    // one for the block with the row, and one for the record (which has both the key
+    // and the value)
+    assert_eq!((0, 2), (stats.hits, stats.misses));
+}
+
+#[test]
+fn test_repeated_gets_hit_the_cache() {
+    let dir = TempDir::new("cache-hits");
+    let path = dir.path();
+    create(&path, &(0..10).collect::<Vec<_>>(), "foo");
+
+    let hf: HashFile<usize, String> = HashFile::new(&path).unwrap().set_cache_size(1 << 20);
+    let stats = || {
+        let stats = hf.cache_stats();
+        (stats.hits, stats.misses)
+    };
+
+    // (the search goes through a bunch of rows, but they're all in the first block)
+    assert_eq!(Some(("foo".to_owned(), 0)), hf.get(&1).unwrap());
+    let (hits, misses) = stats();
+    assert_eq!(2, misses);
+    // (the same rows and the same record)
+    assert_eq!(Some(("foo".to_owned(), 0)), hf.get(&1).unwrap());
+    assert_eq!((2 * hits + 2, 2), stats());
+    // (the rows are in the same block, but the record isn't there yet)
+    assert_eq!(Some(("foo".to_owned(), 0)), hf.get(&2).unwrap());
+    assert_eq!(3, stats().1);
+}
+
+#[test]
+fn test_cache_stays_within_its_size() {
+    let dir = TempDir::new("cache-size");
+    let path = dir.path();
+    let keys = (0..2000).collect::<Vec<_>>();
+    create(&path, &keys, &"foo".repeat(30));
+
+    let size = 8 * 1024;
+    let hf: HashFile<usize, String> = HashFile::new(&path).unwrap().set_cache_size(size);
+    for key in &keys {
+        assert!(hf.get(key).unwrap().is_some());
+        assert!(hf.cache_stats().bytes <= size);
+    }
+
+    // the first ones have been thrown out by now
+    let stats = hf.cache_stats();
+    assert!(stats.bytes > 0);
+    hf.get(&0).unwrap();
+    assert!(hf.cache_stats().misses > stats.misses);
+}
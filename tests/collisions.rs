@@ -0,0 +1,116 @@
+extern crate catalog;
+
+mod common;
+
+use catalog::{HashFile, SearchMode, Text};
+
+use common::TempDir;
+
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hasher};
+
+/// A hasher which throws the same hash for everything (so that all the keys collide).
+#[derive(Clone, Default)]
+struct SameHash;
+
+impl Hasher for SameHash {
+    fn finish(&self) -> u64 {
+        0xdead
+    }
+
+    fn write(&mut self, _: &[u8]) {}
+}
+
+impl BuildHasher for SameHash {
+    type Hasher = SameHash;
+
+    fn build_hasher(&self) -> SameHash {
+        SameHash
+    }
+}
+
+type Map = HashFile<usize, String, Text, SameHash>;
+
+fn insert(hf: &mut Map, model: &mut BTreeMap<usize, (String, usize)>, key: usize, value: &str) {
+    hf.insert(key, value.to_owned()).unwrap();
+    let count = model.get(&key).map_or(0, |&(_, c)| c + 1);
+    model.insert(key, (value.to_owned(), count));
+}
+
+fn remove(hf: &mut Map, model: &mut BTreeMap<usize, (String, usize)>, key: usize) {
+    hf.remove(&key).unwrap();
+    model.remove(&key);
+}
+
+fn check(hf: &mut Map, model: &BTreeMap<usize, (String, usize)>) {
+    for key in 0..50 {
+        assert_eq!(model.get(&key).cloned(), hf.get(&key).unwrap(), "key {}", key);
+        assert_eq!(model.contains_key(&key), hf.contains_key(&key).unwrap(), "key {}", key);
+    }
+
+    assert_eq!(model.len(), hf.len().unwrap());
+    let mut entries = hf.iter().unwrap().map(|e| e.unwrap()).collect::<Vec<_>>();
+    entries.sort();
+    let expected = model.iter().map(|(&k, &(ref v, c))| (k, v.clone(), c)).collect::<Vec<_>>();
+    assert_eq!(expected, entries);
+}
+
+fn check_collisions(name: &str, mode: SearchMode, fence_every: u64) {
+    let dir = TempDir::new(name);
+    let path = dir.path();
+    let mut model = BTreeMap::new();
+    let mut hf: Map = HashFile::with_hasher(&path, SameHash).unwrap()
+                                                            .set_capacity(8)
+                                                            .set_search_mode(mode)
+                                                            .set_fence_index(fence_every)
+                                                            .unwrap();
+
+    // a bunch of runs (and some stuff in memory), all with the same hash
+    for key in 0..30 {
+        insert(&mut hf, &mut model, key, &format!("a{}", key));
+    }
+
+    check(&mut hf, &model);
+
+    for key in (0..30).filter(|k| k % 2 == 0) {
+        insert(&mut hf, &mut model, key, &format!("b{}", key));
+    }
+
+    for key in (0..30).filter(|k| k % 3 == 0) {
+        remove(&mut hf, &mut model, key);
+    }
+
+    check(&mut hf, &model);
+    hf.finish().unwrap();
+    check(&mut hf, &model);
+
+    // overwrite, remove and bring back some keys on top of the main file
+    for key in (0..40).filter(|k| k % 5 == 0) {
+        insert(&mut hf, &mut model, key, &format!("c{}", key));
+    }
+
+    for key in (0..30).filter(|k| k % 7 == 1) {
+        remove(&mut hf, &mut model, key);
+    }
+
+    check(&mut hf, &model);
+    hf.finish().unwrap();
+    check(&mut hf, &model);
+    drop(hf);
+
+    let mut hf: Map = HashFile::with_hasher(&path, SameHash).unwrap()
+                                                            .set_search_mode(mode)
+                                                            .set_fence_index(fence_every)
+                                                            .unwrap();
+    check(&mut hf, &model);
+}
+
+#[test]
+fn test_collisions_with_binary_search() {
+    check_collisions("collisions-binary", SearchMode::Binary, 0);
+}
+
+#[test]
+fn test_collisions_with_interpolation_and_fences() {
+    check_collisions("collisions-interpolation", SearchMode::Interpolation, 4);
+}
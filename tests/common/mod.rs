@@ -0,0 +1,56 @@
+// (each test crate uses only some of these)
+#![allow(dead_code)]
+
+use catalog::HashFile;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// A directory of our own for a test, which is removed along with everything in it when
+/// the test is done (even if it fails).
+pub struct TempDir {
+    dir: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let dir = env::temp_dir().join(format!("catalog-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TempDir {
+            dir: dir,
+        }
+    }
+
+    /// The path of the `HashFile` in this directory.
+    pub fn path(&self) -> String {
+        self.dir.join("map").to_string_lossy().into_owned()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// The names of the files in the directory of the given path.
+pub fn file_names(path: &str) -> Vec<String> {
+    let mut names = fs::read_dir(Path::new(path).parent().unwrap()).unwrap().map(|entry| {
+        entry.unwrap().file_name().to_string_lossy().into_owned()
+    }).collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+/// Create a (finished) `HashFile` in the path, with the same value for all the keys.
+pub fn create(path: &str, keys: &[usize], value: &str) {
+    let mut hf: HashFile<usize, String> = HashFile::new(path).unwrap().set_capacity(keys.len());
+    for &key in keys {
+        hf.insert(key, value.to_owned()).unwrap();
+    }
+
+    hf.finish().unwrap();
+}
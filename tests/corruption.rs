@@ -0,0 +1,132 @@
+extern crate catalog;
+
+mod common;
+
+use catalog::{Error, HashFile, Verification};
+
+use common::{TempDir, create};
+
+use std::fs::{self, OpenOptions};
+
+/// (the size of the header of a "key" file, and that of each of its rows)
+const HEADER_LEN: u64 = 40;
+const ROW_LEN: u64 = 24;
+
+/// Flip a bit of the byte at the given offset of the file.
+fn flip(path: &str, offset: u64) {
+    let mut bytes = fs::read(path).unwrap();
+    bytes[offset as usize] ^= 1;
+    fs::write(path, bytes).unwrap();
+}
+
+#[test]
+fn test_missing_rows_are_corruption() {
+    let dir = TempDir::new("corruption-short");
+    let path = dir.path();
+    create(&path, &[0, 1, 2, 3], "foo");
+
+    // the last row is cut short
+    let file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.set_len(HEADER_LEN + 3 * ROW_LEN + 10).unwrap();
+    drop(file);
+
+    let is_last_row = |result: Result<usize, Error>| match result {
+        Err(Error::CorruptRow { ref path, offset }) => {
+            assert!(path.ends_with("map"));
+            assert_eq!(HEADER_LEN + 3 * ROW_LEN, offset);
+        },
+        result => panic!("unexpected result: {:?}", result),
+    };
+
+    let mut hf: HashFile<usize, String> = HashFile::new(&path).unwrap();
+    is_last_row(hf.iter().unwrap().collect::<Result<Vec<_>, _>>().map(|v| v.len()));
+    hf.insert(7, "bar".to_owned()).unwrap();
+    is_last_row(hf.finish().map(|_| 0));
+    is_last_row(hf.compact().map(|_| 0));
+
+    let report = hf.verify().unwrap();
+    assert_eq!(vec![(path.clone(), HEADER_LEN + 3 * ROW_LEN)], report.bad_rows);
+    // (and the merges didn't go through)
+    assert!(fs::metadata(&path).unwrap().len() < HEADER_LEN + 4 * ROW_LEN);
+}
+
+#[test]
+fn test_damaged_row_is_caught() {
+    let dir = TempDir::new("corruption-row");
+    let path = dir.path();
+    create(&path, &(0..10).collect::<Vec<_>>(), "foo");
+    // (the checksum of the row, so that the hashes are still in order)
+    let offset = HEADER_LEN + 3 * ROW_LEN;
+    flip(&path, offset + 20);
+
+    let hf: HashFile<usize, String> = HashFile::new(&path).unwrap();
+    assert_eq!(Verification {
+        bad_files: vec![path.clone()],
+        bad_rows: vec![(path.clone(), offset)],
+        bad_records: vec![],
+    }, hf.verify().unwrap());
+
+    // we don't know which key is in there, but one of them is
+    let mut damaged = 0;
+    for key in 0..10 {
+        match hf.get(&key) {
+            Ok(Some(_)) => (),
+            Err(Error::ChecksumMismatch { path: ref p, offset: o }) => {
+                assert_eq!((&path, offset), (p, o));
+                damaged += 1;
+            },
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    assert_eq!(1, damaged);
+}
+
+#[test]
+fn test_damaged_record_is_caught() {
+    let dir = TempDir::new("corruption-record");
+    let path = dir.path();
+    create(&path, &(0..10).collect::<Vec<_>>(), "foo");
+    let data_path = format!("{}.dat", path);
+    let data = fs::read(&data_path).unwrap();
+    let offset = data.windows(6).position(|w| w == b"5\0foo\0").unwrap() as u64;
+    // (the value)
+    flip(&data_path, offset + 2);
+
+    let hf: HashFile<usize, String> = HashFile::new(&path).unwrap();
+    assert_eq!(Verification {
+        bad_files: vec![],
+        bad_rows: vec![],
+        bad_records: vec![offset],
+    }, hf.verify().unwrap());
+
+    match hf.get(&5) {
+        Err(Error::ChecksumMismatch { path: ref p, offset: o }) => {
+            assert_eq!((&data_path, offset), (p, o));
+        },
+        result => panic!("unexpected result: {:?}", result),
+    }
+
+    assert_eq!(Some(("foo".to_owned(), 0)), hf.get(&4).unwrap());
+}
+
+#[test]
+fn test_damaged_file_checksum_is_caught() {
+    let dir = TempDir::new("corruption-header");
+    let path = dir.path();
+    create(&path, &(0..10).collect::<Vec<_>>(), "foo");
+    // (the checksum of the whole file, in the header)
+    flip(&path, 32);
+
+    let hf: HashFile<usize, String> = HashFile::new(&path).unwrap();
+    assert_eq!(Verification {
+        bad_files: vec![path.clone()],
+        bad_rows: vec![],
+        bad_records: vec![],
+    }, hf.verify().unwrap());
+
+    // the rows themselves are fine
+    for key in 0..10 {
+        assert_eq!(Some(("foo".to_owned(), 0)), hf.get(&key).unwrap());
+    }
+}
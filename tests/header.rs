@@ -0,0 +1,76 @@
+extern crate catalog;
+
+mod common;
+
+use catalog::{DefaultStableHasher, Error, HashFile, HashFileReader};
+
+use common::{TempDir, create};
+
+use std::fs;
+
+#[test]
+fn test_other_hasher_is_rejected() {
+    let dir = TempDir::new("header-hasher");
+    let path = dir.path();
+    let hasher = DefaultStableHasher::with_keys(1, 2);
+    {
+        let mut hf: HashFile<usize, String, _, _> =
+            HashFile::with_hasher(&path, hasher).unwrap();
+        hf.insert(0, "foo".to_owned()).unwrap();
+        hf.finish().unwrap();
+    }
+
+    match HashFile::<usize, String>::new(&path) {
+        Err(Error::HasherMismatch) => (),
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+
+    match HashFileReader::<usize, String>::new(&path) {
+        Err(Error::HasherMismatch) => (),
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+
+    // (and it's fine with the right one)
+    let hf: HashFile<usize, String, _, _> = HashFile::with_hasher(&path, hasher).unwrap();
+    assert_eq!(Some(("foo".to_owned(), 0)), hf.get(&0).unwrap());
+}
+
+#[test]
+fn test_foreign_file_is_rejected() {
+    let dir = TempDir::new("header-foreign");
+    let path = dir.path();
+    fs::write(&path, "key,value\nfoo,bar\nsome more stuff, so that it's long enough\n").unwrap();
+
+    match HashFile::<usize, String>::new(&path) {
+        Err(Error::InvalidHeader(ref p)) => assert_eq!(&path, p),
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+
+    match HashFileReader::<usize, String>::new(&path) {
+        Err(Error::InvalidHeader(ref p)) => assert_eq!(&path, p),
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_newer_version_is_rejected() {
+    let dir = TempDir::new("header-version");
+    let path = dir.path();
+    create(&path, &[0, 1, 2], "foo");
+
+    // (the version is at 8..10, in little-endian)
+    let mut bytes = fs::read(&path).unwrap();
+    let version = u16::from_le_bytes([bytes[8], bytes[9]]);
+    bytes[8..10].copy_from_slice(&(version + 1).to_le_bytes());
+    fs::write(&path, bytes).unwrap();
+
+    match HashFile::<usize, String>::new(&path) {
+        Err(Error::UnsupportedVersion(v)) => assert_eq!(version + 1, v),
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+
+    match HashFileReader::<usize, String>::new(&path) {
+        Err(Error::UnsupportedVersion(v)) => assert_eq!(version + 1, v),
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+}
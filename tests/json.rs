@@ -0,0 +1,97 @@
+#![cfg(feature = "serde")]
+
+extern crate catalog;
+extern crate serde;
+
+mod common;
+
+use catalog::{HashFile, HashFileReader, Json};
+
+use common::TempDir;
+
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+
+use std::fmt::Debug;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct Point {
+    x: i32,
+    y: i32,
+    label: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+enum Shape {
+    Empty,
+    Circle(f64),
+    Line { from: (i32, i32), to: (i32, i32) },
+}
+
+/// Put the pairs in a `HashFile`, and get them back - from memory, from the files (before
+/// and after finishing), and from a reader.
+fn round_trip<K, V>(name: &str, pairs: Vec<(K, V)>)
+    where K: Serialize + DeserializeOwned + Clone + PartialEq + Debug,
+          V: Serialize + DeserializeOwned + Clone + PartialEq + Debug
+{
+    let dir = TempDir::new(name);
+    let path = dir.path();
+    let check = |hf: &HashFile<K, V, Json>| {
+        for (key, value) in &pairs {
+            assert_eq!(Some((value.clone(), 0)), hf.get(key).unwrap());
+        }
+    };
+
+    let mut hf: HashFile<K, V, Json> =
+        HashFile::with_encoding(&path, Json).unwrap().set_capacity(1000);
+    for (key, value) in &pairs {
+        hf.insert(key.clone(), value.clone()).unwrap();
+    }
+
+    check(&hf);
+    hf.finish().unwrap();
+    check(&hf);
+
+    let entries = hf.iter().unwrap().map(|e| e.unwrap()).collect::<Vec<_>>();
+    assert_eq!(pairs.len(), entries.len());
+    for (key, value, count) in entries {
+        assert!(pairs.contains(&(key, value)));
+        assert_eq!(0, count);
+    }
+    drop(hf);
+
+    let reader: HashFileReader<K, V, Json> = HashFileReader::with_encoding(&path, Json).unwrap();
+    for (key, value) in &pairs {
+        assert_eq!(Some((value.clone(), 0)), reader.get(key).unwrap());
+    }
+}
+
+#[test]
+fn test_struct_and_enum() {
+    let point = |x, y| Point {
+        x: x,
+        y: y,
+        // (with the stuff that needs escaping)
+        label: format!("{}\n{}\0\\", x, y),
+    };
+
+    round_trip("json-struct", vec![
+        (point(0, 0), Shape::Empty),
+        (point(1, -1), Shape::Circle(2.5)),
+        (point(-3, 7), Shape::Line { from: (1, 2), to: (3, 4) }),
+    ]);
+}
+
+#[test]
+fn test_tuple_and_bytes() {
+    round_trip("json-tuple", vec![
+        ((0u8, "zero".to_owned()), vec![0u8, 1, 2]),
+        ((1, "one\r\n".to_owned()), vec![]),
+        ((2, String::new()), (0..=255).collect::<Vec<u8>>()),
+    ]);
+
+    round_trip("json-bytes", vec![
+        (b"\0\n\\".to_vec(), (true, -1i64, Some('x'))),
+        (vec![255; 3], (false, i64::MAX, None)),
+    ]);
+}
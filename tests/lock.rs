@@ -0,0 +1,96 @@
+extern crate catalog;
+
+mod common;
+
+use catalog::{Error, HashFile, HashFileReader, recover};
+
+use common::{TempDir, create};
+
+use std::env;
+use std::process::Command;
+
+/// Tells the child (see `open_in_child`) what it should open, and where.
+const CHILD_MODE: &'static str = "CATALOG_LOCK_TEST_MODE";
+const CHILD_PATH: &'static str = "CATALOG_LOCK_TEST_PATH";
+
+/// This runs in the child process, which opens the file (like the parent asked it to),
+/// and prints whether it got the lock. It doesn't do anything when it's run as a test.
+#[test]
+fn lock_child() {
+    let (mode, path) = match (env::var(CHILD_MODE), env::var(CHILD_PATH)) {
+        (Ok(mode), Ok(path)) => (mode, path),
+        _ => return,
+    };
+
+    let result = match &*mode {
+        "writer" => HashFile::<usize, String>::new(&path).map(|_| ()),
+        "reader" => HashFileReader::<usize, String>::new(&path).map(|_| ()),
+        "recover" => recover(&path).map(|_| ()),
+        _ => panic!("unknown mode: {}", mode),
+    };
+
+    match result {
+        Ok(_) => println!("child: ok"),
+        Err(Error::Locked(_)) => println!("child: locked"),
+        Err(e) => panic!("unexpected error: {:?}", e),
+    }
+}
+
+/// Open the file in another process (by running this test binary once again, with only
+/// `lock_child`), and get whether it was `ok` or `locked`.
+fn open_in_child(mode: &str, path: &str) -> String {
+    let output = Command::new(env::current_exe().unwrap())
+                         .args(["lock_child", "--exact", "--nocapture", "--test-threads=1"])
+                         .env(CHILD_MODE, mode)
+                         .env(CHILD_PATH, path)
+                         .output()
+                         .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "the child failed: {}{}", stdout,
+            String::from_utf8_lossy(&output.stderr));
+    // (the harness could've printed the name of the test on the same line)
+    stdout.split("child: ").nth(1)
+                           .and_then(|rest| rest.split_whitespace().next())
+                           .expect("the child didn't say anything")
+                           .to_owned()
+}
+
+#[test]
+fn test_writer_locks_out_everyone() {
+    let dir = TempDir::new("lock-writer");
+    let path = dir.path();
+    create(&path, &[0], "foo");
+
+    let hf: HashFile<usize, String> = HashFile::new(&path).unwrap();
+    assert_eq!("locked", open_in_child("writer", &path));
+    assert_eq!("locked", open_in_child("reader", &path));
+    assert_eq!("locked", open_in_child("recover", &path));
+
+    drop(hf);
+    assert_eq!("ok", open_in_child("writer", &path));
+    assert_eq!("ok", open_in_child("reader", &path));
+}
+
+#[test]
+fn test_readers_share_the_lock() {
+    let dir = TempDir::new("lock-reader");
+    let path = dir.path();
+    create(&path, &[0], "foo");
+
+    let reader: HashFileReader<usize, String> = HashFileReader::new(&path).unwrap();
+    assert_eq!("ok", open_in_child("reader", &path));
+    assert_eq!("locked", open_in_child("writer", &path));
+    assert_eq!("locked", open_in_child("recover", &path));
+
+    // (and in this process too)
+    let other: HashFileReader<usize, String> = HashFileReader::new(&path).unwrap();
+    assert_eq!(Some(("foo".to_owned(), 0)), other.get(&0).unwrap());
+    match HashFile::<usize, String>::new(&path) {
+        Err(Error::Locked(_)) => (),
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+
+    drop(reader);
+    drop(other);
+    assert_eq!("ok", open_in_child("writer", &path));
+}
@@ -0,0 +1,51 @@
+// (the mapped files are only used with the `mmap` feature)
+#![cfg(feature = "mmap")]
+
+extern crate catalog;
+
+mod common;
+
+use catalog::{HashFile, HashFileReader};
+
+use common::{TempDir, create};
+
+use std::collections::BTreeMap;
+use std::fs;
+
+#[test]
+fn test_lookups_after_appending_past_the_map() {
+    let dir = TempDir::new("mmap-append");
+    let path = dir.path();
+    create(&path, &(0..100).collect::<Vec<_>>(), "old");
+    let mut model = (0..100).map(|k| (k, ("old".to_owned(), 0))).collect::<BTreeMap<_, _>>();
+
+    // the "data" file is mapped while opening, and the flushes append to it
+    let mut hf: HashFile<usize, String> = HashFile::new(&path).unwrap().set_capacity(10);
+    let data_path = format!("{}.dat", path);
+    let mapped_length = fs::metadata(&data_path).unwrap().len();
+    for key in (50..150).filter(|k| k % 3 != 0) {
+        hf.insert(key, format!("new {}", key)).unwrap();
+        let count = model.get(&key).map_or(0, |&(_, c)| c + 1);
+        model.insert(key, (format!("new {}", key), count));
+
+        // (the new records are read as soon as they're flushed)
+        assert_eq!(model.get(&key).cloned(), hf.get(&key).unwrap());
+    }
+
+    assert!(fs::metadata(&data_path).unwrap().len() > mapped_length);
+    let check = |hf: &HashFile<usize, String>| {
+        for key in 0..160 {
+            assert_eq!(model.get(&key).cloned(), hf.get(&key).unwrap(), "key {}", key);
+        }
+    };
+
+    check(&hf);
+    hf.finish().unwrap();
+    check(&hf);
+    drop(hf);
+
+    let reader: HashFileReader<usize, String> = HashFileReader::new(&path).unwrap();
+    for key in 0..160 {
+        assert_eq!(model.get(&key).cloned(), reader.get(&key).unwrap(), "key {}", key);
+    }
+}
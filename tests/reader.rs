@@ -0,0 +1,68 @@
+extern crate catalog;
+
+mod common;
+
+use catalog::{Error, HashFile, HashFileReader};
+
+use common::{TempDir, create};
+
+use std::fs;
+use std::sync::Arc;
+use std::thread;
+
+/// (this only has to compile)
+fn assert_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn test_reader_is_send_and_sync() {
+    assert_send_sync::<HashFileReader<usize, String>>();
+}
+
+#[test]
+fn test_reader_is_shared_among_threads() {
+    let dir = TempDir::new("reader-threads");
+    let path = dir.path();
+    create(&path, &(0..1000).collect::<Vec<_>>(), "foo");
+
+    let reader: Arc<HashFileReader<usize, String>> = Arc::new(HashFileReader::new(&path).unwrap());
+    let handles = (0..4).map(|i| {
+        let reader = reader.clone();
+        thread::spawn(move || {
+            // (each one goes through all the keys, starting at a different place)
+            for key in (0..1100).map(|k| (k + i * 250) % 1100) {
+                let expected = if key < 1000 { Some(("foo".to_owned(), 0)) } else { None };
+                assert_eq!(expected, reader.get(&key).unwrap(), "key {}", key);
+            }
+        })
+    }).collect::<Vec<_>>();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+#[test]
+fn test_bad_utf8_is_corruption() {
+    let dir = TempDir::new("reader-utf8");
+    let path = dir.path();
+    create(&path, &[0], "foo");
+
+    // (the record starts with the key)
+    let data_path = format!("{}.dat", path);
+    let mut data = fs::read(&data_path).unwrap();
+    data[0] = 0xff;
+    fs::write(&data_path, data).unwrap();
+
+    let reader: HashFileReader<usize, String> = HashFileReader::new(&path).unwrap();
+    match reader.get(&0) {
+        Err(Error::CorruptRow { ref path, offset: 0 }) => assert_eq!(&data_path, path),
+        result => panic!("unexpected result: {:?}", result),
+    }
+
+    drop(reader);
+    let hf: HashFile<usize, String> = HashFile::new(&path).unwrap();
+    match hf.get(&0) {
+        Err(Error::CorruptRow { ref path, offset: 0 }) => assert_eq!(&data_path, path),
+        result => panic!("unexpected result: {:?}", result),
+    }
+}
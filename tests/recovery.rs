@@ -0,0 +1,231 @@
+extern crate catalog;
+
+mod common;
+
+use catalog::{Error, HashFile, HashFileReader, Recovery, recover};
+
+use common::{TempDir, create, file_names};
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+#[test]
+fn test_manifest_is_replayed() {
+    let dir = TempDir::new("recovery-manifest");
+    let path = dir.path();
+    create(&path, &[0, 1, 2, 3, 4], "old");
+    {
+        // leave a run behind
+        let mut hf: HashFile<usize, String> = HashFile::new(&path).unwrap().set_capacity(2);
+        for key in 10..13 {
+            hf.insert(key, "run".to_owned()).unwrap();
+        }
+    }
+
+    let runs = file_names(&path).into_iter().filter(|n| n.contains(".run.")).collect::<Vec<_>>();
+    assert!(!runs.is_empty());
+
+    // the new files (which were written and synced before we went down)
+    let new_dir = TempDir::new("recovery-manifest-new");
+    let new_path = new_dir.path();
+    create(&new_path, &[0, 1, 2, 3, 4, 5], "new");
+    fs::copy(&new_path, format!("{}.hash_file", path)).unwrap();
+    // ... and one of them was renamed before we went down
+    fs::copy(format!("{}.dat", new_path), format!("{}.dat", path)).unwrap();
+
+    let mut manifest = "catalog manifest 1\n".to_owned();
+    manifest.push_str("rename\0map.dat.hash_file\0map.dat\n");
+    manifest.push_str("rename\0map.hash_file\0map\n");
+    for run in &runs {
+        manifest.push_str(&format!("remove\0{}\n", run));
+    }
+
+    fs::write(format!("{}.manifest", path), manifest).unwrap();
+
+    let recovery = recover(&path).unwrap();
+    assert_eq!(Recovery {
+        finished_commit: true,
+        removed_files: vec![],
+        truncated_bytes: 0,
+        log_entries: 0,
+    }, recovery);
+    assert_eq!(vec!["map", "map.dat", "map.lock"], file_names(&path));
+    assert_eq!(fs::read(&new_path).unwrap(), fs::read(&path).unwrap());
+
+    let hf: HashFile<usize, String> = HashFile::new(&path).unwrap();
+    assert!(hf.recovery().is_clean());
+    assert_eq!(Some(("new".to_owned(), 0)), hf.get(&5).unwrap());
+    assert_eq!(None, hf.get(&10).unwrap());
+    assert_eq!(6, hf.len().unwrap());
+}
+
+#[test]
+fn test_bad_manifest_is_rejected() {
+    let dir = TempDir::new("recovery-bad-manifest");
+    let path = dir.path();
+    create(&path, &[0, 1, 2], "old");
+    fs::write(format!("{}.manifest", path), "catalog manifest 0\nrename\0a\0b\n").unwrap();
+
+    match recover(&path) {
+        Err(Error::InvalidHeader(ref manifest)) => assert!(manifest.ends_with("map.manifest")),
+        result => panic!("unexpected result: {:?}", result),
+    }
+
+    // nothing's been touched
+    assert_eq!(vec!["map", "map.dat", "map.lock", "map.manifest"], file_names(&path));
+}
+
+#[test]
+fn test_reader_needs_recovery() {
+    let dir = TempDir::new("recovery-reader");
+    let path = dir.path();
+    create(&path, &[0, 1, 2], "old");
+    fs::write(format!("{}.manifest", path), "catalog manifest 1\n").unwrap();
+
+    match HashFileReader::<usize, String>::new(&path) {
+        Err(Error::NeedsRecovery(ref p)) => assert_eq!(&path, p),
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+
+    assert!(recover(&path).unwrap().finished_commit);
+    let reader: HashFileReader<usize, String> = HashFileReader::new(&path).unwrap();
+    assert_eq!(Some(("old".to_owned(), 0)), reader.get(&1).unwrap());
+}
+
+#[test]
+fn test_log_is_replayed_and_truncated() {
+    let dir = TempDir::new("recovery-log");
+    let path = dir.path();
+    create(&path, &[0, 1, 2], "old");
+    {
+        let mut hf: HashFile<usize, String> =
+            HashFile::new(&path).map(|hf| hf.set_capacity(1000))
+                                .and_then(|hf| hf.set_write_ahead_log(true)).unwrap();
+        hf.insert(1, "new".to_owned()).unwrap();
+        hf.insert(5, "five".to_owned()).unwrap();
+        hf.remove(&2).unwrap();
+    }
+
+    // we went down in the middle of writing an entry
+    let log_path = format!("{}.wal", path);
+    let log_length = fs::metadata(&log_path).unwrap().len();
+    let mut log = OpenOptions::new().append(true).open(&log_path).unwrap();
+    log.write_all(b"1234\x007\x00cut sh").unwrap();
+    drop(log);
+
+    let hf: HashFile<usize, String> = HashFile::new(&path).unwrap();
+    assert_eq!(Recovery {
+        finished_commit: false,
+        removed_files: vec![],
+        truncated_bytes: 0,
+        log_entries: 3,
+    }, *hf.recovery());
+    assert_eq!(log_length, fs::metadata(&log_path).unwrap().len());
+
+    assert_eq!(Some(("old".to_owned(), 0)), hf.get(&0).unwrap());
+    assert_eq!(Some(("new".to_owned(), 1)), hf.get(&1).unwrap());
+    assert_eq!(None, hf.get(&2).unwrap());
+    assert_eq!(Some(("five".to_owned(), 0)), hf.get(&5).unwrap());
+    assert_eq!(None, hf.get(&7).unwrap());
+}
+
+#[test]
+fn test_leftovers_are_removed() {
+    let dir = TempDir::new("recovery-leftovers");
+    let path = dir.path();
+    create(&path, &[0, 1, 2], "old");
+
+    let leftovers = [".hash_file", ".dat.hash_file", ".run.3.hash_file", ".bloom.hash_file",
+                     ".manifest.hash_file", ".spill.0", ".spill.12"];
+    // (these aren't ours)
+    let others = [".spill.x", ".run.hash_file", ".notes", "x.hash_file"];
+    for suffix in leftovers.iter().chain(others.iter()) {
+        fs::write(format!("{}{}", path, suffix), "stuff").unwrap();
+    }
+
+    let mut removed = leftovers.iter().map(|s| format!("{}{}", path, s)).collect::<Vec<_>>();
+    removed.sort();
+    let recovery = recover(&path).unwrap();
+    assert_eq!(Recovery {
+        finished_commit: false,
+        removed_files: removed,
+        truncated_bytes: 0,
+        log_entries: 0,
+    }, recovery);
+
+    let mut remaining = vec!["map".to_owned(), "map.dat".to_owned(), "map.lock".to_owned()];
+    remaining.extend(others.iter().map(|s| format!("map{}", s)));
+    remaining.sort();
+    assert_eq!(remaining, file_names(&path));
+
+    // there's nothing left to clean up
+    assert!(recover(&path).unwrap().is_clean());
+    let hf: HashFile<usize, String> = HashFile::new(&path).unwrap();
+    assert!(hf.recovery().is_clean());
+    assert_eq!(Some(("old".to_owned(), 0)), hf.get(&2).unwrap());
+}
+
+#[test]
+fn test_new_cleans_up_the_leftovers() {
+    let dir = TempDir::new("recovery-new");
+    let path = dir.path();
+    create(&path, &[0, 1, 2], "old");
+    fs::write(format!("{}.hash_file", path), "stuff").unwrap();
+    fs::write(format!("{}.spill.1", path), "stuff").unwrap();
+
+    let hf: HashFile<usize, String> = HashFile::new(&path).unwrap();
+    assert_eq!(vec![format!("{}.hash_file", path), format!("{}.spill.1", path)],
+               hf.recovery().removed_files);
+    assert_eq!(vec!["map", "map.dat", "map.lock"], file_names(&path));
+}
+
+#[test]
+fn test_foreign_files_are_left_alone() {
+    let dir = TempDir::new("recovery-foreign");
+    let path = dir.path();
+    fs::write(&path, "this isn't ours, and neither are the files next to it").unwrap();
+    fs::write(format!("{}.hash_file", path), "stuff").unwrap();
+
+    match HashFile::<usize, String>::new(&path) {
+        Err(Error::InvalidHeader(ref p)) => assert_eq!(&path, p),
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+
+    match recover(&path) {
+        Err(Error::InvalidHeader(ref p)) => assert_eq!(&path, p),
+        result => panic!("unexpected result: {:?}", result),
+    }
+
+    assert_eq!(vec!["map", "map.hash_file", "map.lock"], file_names(&path));
+}
+
+#[test]
+fn test_torn_record_is_cut_off() {
+    let dir = TempDir::new("recovery-torn");
+    let path = dir.path();
+    create(&path, &[0, 1, 2], "old");
+
+    // we went down in the middle of appending a record
+    let data_path = format!("{}.dat", path);
+    let data_length = fs::metadata(&data_path).unwrap().len();
+    let mut data = OpenOptions::new().append(true).open(&data_path).unwrap();
+    data.write_all(b"7\x00torn va").unwrap();
+    drop(data);
+
+    let mut hf: HashFile<usize, String> = HashFile::new(&path).unwrap();
+    assert_eq!(Recovery {
+        finished_commit: false,
+        removed_files: vec![],
+        truncated_bytes: 9,
+        log_entries: 0,
+    }, *hf.recovery());
+    assert_eq!(data_length, fs::metadata(&data_path).unwrap().len());
+
+    // the new records go right after the old ones
+    hf.insert(7, "new".to_owned()).unwrap();
+    assert!(hf.verify().unwrap().is_ok());
+    hf.finish().unwrap();
+    assert!(hf.verify().unwrap().is_ok());
+    assert_eq!(Some(("old".to_owned(), 0)), hf.get(&2).unwrap());
+    assert_eq!(Some(("new".to_owned(), 0)), hf.get(&7).unwrap());
+}
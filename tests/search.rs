@@ -0,0 +1,52 @@
+extern crate catalog;
+
+mod common;
+
+use catalog::{HashFile, HashFileReader, SearchMode};
+
+use common::TempDir;
+
+const KEYS: usize = 20000;
+
+/// Look for all the keys (and a bunch of missing ones) in the file, with the given settings.
+fn check_search(path: &str, mode: SearchMode, fence_every: u64) {
+    let hf: HashFile<usize, String> = HashFile::new(path).unwrap()
+                                                         .set_search_mode(mode)
+                                                         .set_fence_index(fence_every)
+                                                         .unwrap();
+    for key in 0..KEYS {
+        assert_eq!(Some((format!("v{}", key % 7), 0)), hf.get(&key).unwrap(), "key {}", key);
+    }
+
+    for key in KEYS..KEYS + 2000 {
+        assert_eq!(None, hf.get(&key).unwrap(), "key {}", key);
+        assert!(!hf.contains_key(&key).unwrap(), "key {}", key);
+    }
+
+    drop(hf);
+    let reader: HashFileReader<usize, String> = HashFileReader::new(path).unwrap()
+                                                                        .set_search_mode(mode);
+    for key in (0..KEYS + 2000).filter(|k| k % 13 == 0) {
+        let expected = if key < KEYS { Some((format!("v{}", key % 7), 0)) } else { None };
+        assert_eq!(expected, reader.get(&key).unwrap(), "key {}", key);
+    }
+}
+
+#[test]
+fn test_search_with_uniform_hashes() {
+    let dir = TempDir::new("search-uniform");
+    let path = dir.path();
+    {
+        let mut hf: HashFile<usize, String> = HashFile::new(&path).unwrap().set_capacity(KEYS);
+        for key in 0..KEYS {
+            hf.insert(key, format!("v{}", key % 7)).unwrap();
+        }
+
+        hf.finish().unwrap();
+    }
+
+    check_search(&path, SearchMode::Binary, 0);
+    check_search(&path, SearchMode::Interpolation, 0);
+    check_search(&path, SearchMode::Interpolation, 64);
+}
+